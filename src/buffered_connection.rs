@@ -12,18 +12,38 @@ pub struct BufferedConnection {
 }
 
 impl BufferedConnection {
-    /// Actually send the buffered data, if any.
+    /// Actually send the buffered data, if any. This waits until the writer
+    /// has flushed it too, so nothing is lost if the process exits straight
+    /// after e.g. a stop reply.
     pub async fn flush<W: tokio::io::AsyncWriteExt + Unpin>(
         &mut self,
         writer: &mut W,
     ) -> io::Result<()> {
-        if self.flush_pending {
+        // Send everything even if gdbstub didn't ask for a flush, since
+        // nothing is written until the next call otherwise.
+        if self.flush_pending || !self.buffer.is_empty() {
             writer.write_all(&self.buffer).await?;
+            writer.flush().await?;
             self.buffer.clear();
             self.flush_pending = false;
         }
         Ok(())
     }
+
+    /// Queue `text` to be printed on the GDB console. This sends an `O`
+    /// packet, which is only allowed while the target is running, i.e. just
+    /// before a stop reply.
+    pub fn console_output(&mut self, text: &str) {
+        let mut payload = String::from("O");
+        for byte in text.bytes() {
+            payload.push_str(&format!("{byte:02x}"));
+        }
+        let checksum = payload
+            .bytes()
+            .fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.buffer
+            .extend_from_slice(format!("${payload}#{checksum:02x}").as_bytes());
+    }
 }
 
 impl Connection for BufferedConnection {
@@ -40,3 +60,45 @@ impl Connection for BufferedConnection {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt as _;
+
+    use super::*;
+
+    #[test]
+    fn console_output_packet() {
+        let mut conn = BufferedConnection::default();
+        conn.console_output("hi\n");
+        assert_eq!(conn.buffer, b"$O68690a#bd");
+    }
+
+    /// Flush `conn` into a pipe and return what the other end receives.
+    async fn flushed(conn: &mut BufferedConnection) -> Vec<u8> {
+        let (mut ours, mut gdb) = tokio::io::duplex(64);
+        conn.flush(&mut ours).await.unwrap();
+        drop(ours);
+        let mut received = Vec::new();
+        gdb.read_to_end(&mut received).await.unwrap();
+        received
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn stop_reply_is_sent_on_flush() {
+        let mut conn = BufferedConnection::default();
+        for &byte in b"$T05#b9" {
+            Connection::write(&mut conn, byte).unwrap();
+        }
+        Connection::flush(&mut conn).unwrap();
+        // All of it is sent without GDB sending anything first.
+        assert_eq!(flushed(&mut conn).await, b"$T05#b9");
+        assert!(conn.buffer.is_empty());
+
+        // Buffered output is sent even if gdbstub didn't ask for a flush.
+        conn.console_output("hi\n");
+        assert_eq!(flushed(&mut conn).await, b"$O68690a#bd");
+
+        assert_eq!(flushed(&mut conn).await, b"");
+    }
+}
@@ -0,0 +1,252 @@
+//! Decoding of CHERIoT capabilities.
+//!
+//! A CHERIoT capability is a 32-bit address plus 32 bits of metadata (and
+//! an out-of-band tag). The metadata is laid out as:
+//!
+//! ```text
+//!  31 | 30     25 | 24   22 | 21  18 | 17   9 | 8    0
+//!  R  | perms     | otype   | E      | T      | B
+//! ```
+//!
+//! See the CHERIoT ISA specification for details.
+//!
+//! This is only used by `monitor cap`. Register reads from the debugger just
+//! return the address since the CHERI target descriptions aren't used yet.
+
+use std::fmt;
+
+/// Architectural permissions, in the order they are displayed.
+pub const PERMISSION_NAMES: [&str; 12] = [
+    "GL", "LD", "SD", "MC", "SL", "LM", "LG", "SR", "EX", "U0", "SE", "US",
+];
+
+pub const PERM_GL: u16 = 1 << 0;
+pub const PERM_LD: u16 = 1 << 1;
+pub const PERM_SD: u16 = 1 << 2;
+pub const PERM_MC: u16 = 1 << 3;
+pub const PERM_SL: u16 = 1 << 4;
+pub const PERM_LM: u16 = 1 << 5;
+pub const PERM_LG: u16 = 1 << 6;
+pub const PERM_SR: u16 = 1 << 7;
+pub const PERM_EX: u16 = 1 << 8;
+pub const PERM_U0: u16 = 1 << 9;
+pub const PERM_SE: u16 = 1 << 10;
+pub const PERM_US: u16 = 1 << 11;
+
+/// The decoded fields of a capability.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapFields {
+    pub address: u64,
+    pub base: u64,
+    /// The top is exclusive so it may be 2^32.
+    pub top: u64,
+    /// Bitmask of `PERM_*`.
+    pub permissions: u16,
+    pub otype: u8,
+}
+
+/// Decode the compressed 6-bit permission field.
+fn decode_permissions(p: u8) -> u16 {
+    let bit = |n: u8, perm: u16| if p & (1 << n) != 0 { perm } else { 0 };
+
+    let global = bit(5, PERM_GL);
+
+    let format_bits = match p & 0x1f {
+        // Capability read-write.
+        p if p >> 3 == 0b11 => {
+            PERM_LD | PERM_MC | PERM_SD | bit(2, PERM_SL) | bit(1, PERM_LM) | bit(0, PERM_LG)
+        }
+        // Capability read-only.
+        p if p >> 2 == 0b101 => PERM_LD | PERM_MC | bit(1, PERM_LM) | bit(0, PERM_LG),
+        // Capability write-only.
+        0b10000 => PERM_SD | PERM_MC,
+        // Data only.
+        p if p >> 2 == 0b100 => bit(1, PERM_LD) | bit(0, PERM_SD),
+        // Executable.
+        p if p >> 3 == 0b01 => {
+            PERM_EX | PERM_LD | PERM_MC | bit(2, PERM_SR) | bit(1, PERM_LM) | bit(0, PERM_LG)
+        }
+        // Sealing.
+        _ => bit(2, PERM_U0) | bit(1, PERM_SE) | bit(0, PERM_US),
+    };
+
+    global | format_bits
+}
+
+/// Decode the 32-bit metadata `meta` of the CHERIoT capability with
+/// address `addr`.
+pub fn decode_capability(meta: u64, addr: u64) -> CapFields {
+    let addr = addr & 0xffff_ffff;
+
+    let b = meta & 0x1ff;
+    let t = (meta >> 9) & 0x1ff;
+    let e = match (meta >> 18) & 0xf {
+        0xf => 24,
+        e => e,
+    };
+    let otype = ((meta >> 22) & 0x7) as u8;
+    let permissions = decode_permissions(((meta >> 25) & 0x3f) as u8);
+
+    // Reconstruct the upper bits of base and top from the address, with
+    // corrections for when they are in a different 2^(e+9) aligned region.
+    let a_top = addr >> (e + 9);
+    let a_mid = (addr >> e) & 0x1ff;
+    let a_hi = (a_mid < b) as u64;
+    let t_hi = (t < b) as u64;
+
+    let base_top = a_top.wrapping_sub(a_hi);
+    let top_top = (a_top + t_hi).wrapping_sub(a_hi);
+
+    let base = (base_top.wrapping_shl(e as u32 + 9) | (b << e)) & 0xffff_ffff;
+    let top = (top_top.wrapping_shl(e as u32 + 9) | (t << e)) & 0x1_ffff_ffff;
+
+    CapFields {
+        address: addr,
+        base,
+        top,
+        permissions,
+        otype,
+    }
+}
+
+impl CapFields {
+    pub fn length(&self) -> u64 {
+        self.top.saturating_sub(self.base)
+    }
+
+    /// Whether the address is within bounds.
+    pub fn in_bounds(&self) -> bool {
+        (self.base..self.top).contains(&self.address)
+    }
+}
+
+impl fmt::Display for CapFields {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#010x} [{:#010x}-{:#010x}] (length {:#x}{})",
+            self.address,
+            self.base,
+            self.top,
+            self.length(),
+            if self.in_bounds() {
+                ""
+            } else {
+                ", out of bounds"
+            },
+        )?;
+
+        write!(f, " perms:")?;
+        for (i, name) in PERMISSION_NAMES.iter().enumerate() {
+            if self.permissions & (1 << i) != 0 {
+                write!(f, " {name}")?;
+            }
+        }
+
+        match self.otype {
+            0 => write!(f, " unsealed"),
+            otype if self.permissions & PERM_EX != 0 => write!(f, " otype: {otype} (sentry)"),
+            // Data otypes are offset by 8.
+            otype => write!(f, " otype: {}", otype + 8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode capability metadata from its fields.
+    fn meta(b: u64, t: u64, e: u64, otype: u64, perms: u64) -> u64 {
+        b | t << 9 | e << 18 | otype << 22 | perms << 25
+    }
+
+    #[test]
+    fn bounds_e0() {
+        let cap = decode_capability(meta(0x100, 0x180, 0, 0, 0), 0x1000_0110);
+        assert_eq!(cap.address, 0x1000_0110);
+        assert_eq!(cap.base, 0x1000_0100);
+        assert_eq!(cap.top, 0x1000_0180);
+        assert_eq!(cap.length(), 0x80);
+        assert!(cap.in_bounds());
+    }
+
+    #[test]
+    fn bounds_e15_is_e24() {
+        // The root capability covers the whole address space, so the top
+        // is 2^32.
+        let cap = decode_capability(meta(0, 0x100, 15, 0, 0), 0x8000_0000);
+        assert_eq!(cap.base, 0);
+        assert_eq!(cap.top, 0x1_0000_0000);
+    }
+
+    #[test]
+    fn bounds_corrections() {
+        // T < B so the top is in the next 2^(E+9) region.
+        let encoding = meta(0x1f0, 0x010, 0, 0, 0);
+
+        // The address is in the same region as the base: only t_hi is set.
+        let cap = decode_capability(encoding, 0x1000_01f8);
+        assert_eq!((cap.base, cap.top), (0x1000_01f0, 0x1000_0210));
+
+        // The address is in the same region as the top: a_hi is set too.
+        let cap = decode_capability(encoding, 0x1000_0205);
+        assert_eq!((cap.base, cap.top), (0x1000_01f0, 0x1000_0210));
+        assert!(cap.in_bounds());
+    }
+
+    #[test]
+    fn bounds_larger_exponent() {
+        // E = 4 so B and T are in units of 16 bytes.
+        let cap = decode_capability(meta(0x010, 0x020, 4, 0, 0), 0x2000_0150);
+        assert_eq!((cap.base, cap.top), (0x2000_0100, 0x2000_0200));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        let cap = decode_capability(meta(0x100, 0x180, 0, 0, 0), 0x1000_0180);
+        assert!(!cap.in_bounds());
+        assert!(cap.to_string().contains("out of bounds"));
+    }
+
+    #[test]
+    fn permission_formats() {
+        // Capability read-write.
+        assert_eq!(
+            decode_permissions(0b1_11111),
+            PERM_GL | PERM_LD | PERM_MC | PERM_SD | PERM_SL | PERM_LM | PERM_LG
+        );
+        assert_eq!(decode_permissions(0b0_11000), PERM_LD | PERM_MC | PERM_SD);
+        // Capability read-only.
+        assert_eq!(
+            decode_permissions(0b0_10111),
+            PERM_LD | PERM_MC | PERM_LM | PERM_LG
+        );
+        // Capability write-only.
+        assert_eq!(decode_permissions(0b0_10000), PERM_SD | PERM_MC);
+        // Data only.
+        assert_eq!(decode_permissions(0b0_10011), PERM_LD | PERM_SD);
+        assert_eq!(decode_permissions(0b0_10010), PERM_LD);
+        assert_eq!(decode_permissions(0b0_10001), PERM_SD);
+        // Executable.
+        assert_eq!(
+            decode_permissions(0b1_01111),
+            PERM_GL | PERM_EX | PERM_LD | PERM_MC | PERM_SR | PERM_LM | PERM_LG
+        );
+        assert_eq!(decode_permissions(0b0_01000), PERM_EX | PERM_LD | PERM_MC);
+        // Sealing.
+        assert_eq!(decode_permissions(0b0_00111), PERM_U0 | PERM_SE | PERM_US);
+        assert_eq!(decode_permissions(0b1_00000), PERM_GL);
+    }
+
+    #[test]
+    fn display() {
+        let exec = decode_capability(meta(0x100, 0x180, 0, 1, 0b0_01000), 0x1000_0110);
+        assert_eq!(
+            exec.to_string(),
+            "0x10000110 [0x10000100-0x10000180] (length 0x80) perms: LD MC EX otype: 1 (sentry)"
+        );
+        let data = decode_capability(meta(0x100, 0x180, 0, 1, 0b0_10011), 0x1000_0110);
+        assert!(data.to_string().ends_with("perms: LD SD otype: 9"));
+    }
+}
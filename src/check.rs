@@ -0,0 +1,266 @@
+//! Consistency checks for catching traces that are corrupt, misaligned or
+//! otherwise can't be trusted. Run with `--check`.
+
+use std::fmt;
+
+use num_traits::{Num, ToPrimitive};
+
+use crate::decode::{self, BranchTarget, instruction_length};
+use crate::trace::TraceEvent;
+
+/// Something wrong with a trace event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub index: usize,
+    // Line in the trace file.
+    pub line: u64,
+    pub pc: u64,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "event {} (line {}, pc {:#x}): {}",
+            self.index, self.line, self.pc, self.message
+        )
+    }
+}
+
+/// Whether the instruction is a jump that writes the return address to its
+/// destination register.
+fn is_jump_and_link(instruction: u32, xlen: usize) -> bool {
+    let is = decode::matcher(instruction);
+
+    if instruction_length(instruction) == 4 {
+        is(riscv_opcodes::MASK_JAL, riscv_opcodes::MATCH_JAL)
+            || is(riscv_opcodes::MASK_JALR, riscv_opcodes::MATCH_JALR)
+    } else {
+        // `c.jal` is `c.addiw` on RV64.
+        (xlen == 32 && is(riscv_opcodes::MASK_C_JAL, riscv_opcodes::MATCH_C_JAL))
+            || is(riscv_opcodes::MASK_C_JALR, riscv_opcodes::MATCH_C_JALR)
+    }
+}
+
+/// Check that the return address written by a `jal`/`jalr` is the address
+/// of the following instruction.
+fn check_return_address<Usize: Num + Copy + ToPrimitive>(
+    event: &TraceEvent<Usize>,
+) -> Option<String> {
+    let instruction = event.instruction?;
+    let xwrite = event.xwrite.as_ref()?;
+    let xlen = size_of::<Usize>() * 8;
+
+    // A trapping jump doesn't write its destination.
+    if event.trap || !is_jump_and_link(instruction, xlen) {
+        return None;
+    }
+
+    let pc = event.pc.to_u64()?;
+    let mask = u64::MAX >> (64 - xlen);
+    let expected = pc.wrapping_add(instruction_length(instruction)) & mask;
+    let actual = xwrite.value.to_u64()?;
+
+    (actual != expected).then(|| {
+        format!(
+            "{} wrote return address {actual:#x} to x{} but expected {expected:#x}",
+            event.mnemonic(),
+            xwrite.index
+        )
+    })
+}
+
+/// Check that a direct branch or jump went to its target (or for a
+/// conditional branch, the next instruction). An interrupt taken straight
+/// after a branch will also be reported.
+fn check_branch_target<Usize: Num + Copy + ToPrimitive>(
+    event: &TraceEvent<Usize>,
+    next: &TraceEvent<Usize>,
+) -> Option<String> {
+    let instruction = event.instruction?;
+    if event.trap {
+        return None;
+    }
+    let xlen = size_of::<Usize>() * 8;
+    let pc = event.pc.to_u64()?;
+    let next_pc = next.pc.to_u64()?;
+    let mask = u64::MAX >> (64 - xlen);
+    let fallthrough = pc.wrapping_add(instruction_length(instruction)) & mask;
+
+    let message = match decode::branch_target(instruction, pc, xlen)? {
+        BranchTarget::Jump(target) if next_pc != target => {
+            format!("jumped to {next_pc:#x} but its target is {target:#x}")
+        }
+        BranchTarget::Branch(target) if next_pc != target && next_pc != fallthrough => {
+            format!(
+                "went to {next_pc:#x} but expected {target:#x} (taken) or {fallthrough:#x} (not taken)"
+            )
+        }
+        _ => return None,
+    };
+    Some(format!("{} {message}", event.mnemonic()))
+}
+
+/// Run all the checks on `trace`.
+pub fn check_trace<Usize: Num + Copy + ToPrimitive>(trace: &[TraceEvent<Usize>]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    for (index, event) in trace.iter().enumerate() {
+        let next = trace.get(index + 1);
+        let messages = [
+            check_return_address(event),
+            next.and_then(|next| check_branch_target(event, next)),
+        ];
+        for message in messages.into_iter().flatten() {
+            problems.push(Problem {
+                index,
+                line: event.line,
+                pc: event.pc.to_u64().unwrap_or_default(),
+                message,
+            });
+        }
+    }
+
+    problems
+}
+
+/// Version of the `--check --json` report. Bump this if fields are removed
+/// or change meaning; adding fields is fine.
+pub const JSON_REPORT_VERSION: u32 = 1;
+
+/// Quote and escape a string for JSON.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A machine readable report of `check_trace()`, for CI. For example:
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "events": 2,
+///   "time": {"first": 10, "last": 20},
+///   "cycle": {"first": 1, "last": 2},
+///   "stores": 0,
+///   "register_writes": 2,
+///   "traps": 0,
+///   "anomalies": [
+///     {"index": 1, "line": 3, "pc": 24, "message": "..."}
+///   ]
+/// }
+/// ```
+///
+/// `time` and `cycle` are `null` for an empty trace.
+pub fn json_report<Usize>(trace: &[TraceEvent<Usize>], problems: &[Problem]) -> String {
+    let span = |field: fn(&TraceEvent<Usize>) -> u64| match (trace.first(), trace.last()) {
+        (Some(first), Some(last)) => {
+            format!("{{\"first\": {}, \"last\": {}}}", field(first), field(last))
+        }
+        _ => "null".to_owned(),
+    };
+    let count = |pred: fn(&TraceEvent<Usize>) -> bool| trace.iter().filter(|e| pred(e)).count();
+
+    let anomalies: Vec<String> = problems
+        .iter()
+        .map(|problem| {
+            format!(
+                "    {{\"index\": {}, \"line\": {}, \"pc\": {}, \"message\": {}}}",
+                problem.index,
+                problem.line,
+                problem.pc,
+                json_string(&problem.message)
+            )
+        })
+        .collect();
+    let anomalies = if anomalies.is_empty() {
+        "[]".to_owned()
+    } else {
+        format!("[\n{}\n  ]", anomalies.join(",\n"))
+    };
+
+    format!(
+        "{{\n  \"version\": {JSON_REPORT_VERSION},\n  \"events\": {},\n  \"time\": {},\n  \"cycle\": {},\n  \"stores\": {},\n  \"register_writes\": {},\n  \"traps\": {},\n  \"anomalies\": {anomalies}\n}}",
+        trace.len(),
+        span(|e| e.time),
+        span(|e| e.cycle),
+        count(|e| e.store.is_some()),
+        count(|e| e.xwrite.is_some() || e.vwrite.is_some()),
+        count(|e| e.trap),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ibex_trace;
+    use crate::test_util::fixture;
+
+    fn check_fixture(name: &str) -> Vec<Problem> {
+        check_trace(&ibex_trace::read_trace::<u32>(&fixture(name), &Default::default()).unwrap())
+    }
+
+    #[test]
+    fn wrong_return_address() {
+        // The `jal` is fine but the `c.jalr` writes the wrong address.
+        assert_eq!(
+            check_fixture("bad_return.log"),
+            [Problem {
+                index: 1,
+                line: 3,
+                pc: 0x18,
+                message: "c.jalr wrote return address 0x100 to x1 but expected 0x1a".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn wrong_branch_target() {
+        // All but the second `c.j` went to their targets (or fell through).
+        let problems = check_fixture("branches.log");
+        assert_eq!(
+            problems,
+            [Problem {
+                index: 3,
+                line: 5,
+                pc: 0x1c,
+                message: "c.j jumped to 0x30 but its target is 0x18".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn json_report_clean_trace() {
+        let trace =
+            ibex_trace::read_trace::<u32>(&fixture("loop.log"), &Default::default()).unwrap();
+        let problems = check_trace(&trace);
+        assert!(problems.is_empty());
+        assert_eq!(
+            json_report(&trace, &problems),
+            r#"{
+  "version": 1,
+  "events": 15,
+  "time": {"first": 10, "last": 150},
+  "cycle": {"first": 1, "last": 15},
+  "stores": 1,
+  "register_writes": 8,
+  "traps": 0,
+  "anomalies": []
+}"#
+        );
+    }
+}
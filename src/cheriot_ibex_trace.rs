@@ -1,13 +1,12 @@
-use std::{
-    fs::File,
-    io::{BufRead as _, BufReader},
-    path::Path,
-};
+use std::path::Path;
 
 use anyhow::{Context, Result, anyhow, bail};
 use num_traits::Num;
 
-use crate::trace::{Data, MemWrite, TraceEvent, XRegWrite};
+use crate::trace::{
+    CapMetadata, Data, MemWrite, ReadOptions, TraceColumns, TraceEvent, UnknownTokens, VRegWrite,
+    XRegWrite, has_trap_marker, is_failed_store_conditional, read_trace_file, xreg_write_token,
+};
 
 /// Strip the '0x' hex prefix or return an error if it isn't present.
 fn strip_hex_prefix(val: &str) -> Result<&str> {
@@ -20,36 +19,60 @@ fn parse_hex<U: Num>(val: &str) -> Result<U> {
     U::from_str_radix(val, 16).map_err(|_| anyhow!("invalid hex integer {val:?}"))
 }
 
-fn read_line<Usize: Num>(line: &str) -> Result<TraceEvent<Usize>> {
+/// Split capability metadata like `0x112345678` into the hex metadata
+/// (without the prefix) and the tag. The metadata starts with an extra 0
+/// or 1 for the tag.
+fn split_tag(metadata: &str) -> Result<(&str, bool)> {
+    let metadata = strip_hex_prefix(metadata)?;
+    if let Some(metadata) = metadata.strip_prefix('0') {
+        Ok((metadata, false))
+    } else if let Some(metadata) = metadata.strip_prefix('1') {
+        Ok((metadata, true))
+    } else {
+        bail!("Invalid metadata, doesn't start 0x1 or 0x0");
+    }
+}
+
+fn read_line<Usize: Num>(
+    line: &str,
+    columns: &TraceColumns,
+    unknown: &mut UnknownTokens,
+) -> Result<TraceEvent<Usize>> {
     let parts: Vec<&str> = line.split('\t').collect();
 
-    if parts.len() < 4 {
+    if parts.len() < columns.required_len() {
         bail!(
-            "expected at least 4 tab-separated values, got {}",
+            "expected at least {} tab-separated values, got {}",
+            columns.required_len(),
             parts.len()
         );
     }
 
-    let time_str = parts[0].trim();
-    let cycle_str = parts[1].trim();
-    let pc_str = parts[2];
-    let instruction_str = parts[3].trim();
+    let time_str = parts[columns.time].trim();
+    let cycle_str = parts[columns.cycle].trim();
+    let pc_str = parts[columns.pc];
+    let instruction_str = parts[columns.insn].trim();
 
-    let time =
-        u64::from_str_radix(time_str, 10).with_context(|| format!("parsing {time_str:?}"))?;
-    let cycle =
-        u64::from_str_radix(cycle_str, 10).with_context(|| format!("parsing {cycle_str:?}"))?;
+    let time: u64 = time_str
+        .parse()
+        .with_context(|| format!("parsing {time_str:?}"))?;
+    let cycle: u64 = cycle_str
+        .parse()
+        .with_context(|| format!("parsing {cycle_str:?}"))?;
     let pc = parse_hex(pc_str)?;
     let instruction = parse_hex(instruction_str)?;
 
-    let assembly_mnemonic = parts.get(4).map(|s| s.to_owned());
-    let assembly_args = parts.get(5).map(|s| s.to_owned());
+    let column = |index: Option<usize>| index.and_then(|i| parts.get(i)).map(|s| s.to_owned());
+
+    let assembly_mnemonic = column(columns.mnemonic);
+    let assembly_args = column(columns.args);
 
-    let accesses = parts.get(6).map(|s| s.to_owned());
+    let accesses = column(columns.accesses);
 
     let mut phys_addr = None;
     let mut store_val = None;
     let mut xwrite = None;
+    let mut vwrite = None;
 
     if let Some(accesses) = accesses {
         let access_parts = accesses.split_ascii_whitespace();
@@ -67,17 +90,8 @@ fn read_line<Usize: Num>(line: &str) -> Result<TraceEvent<Usize>> {
                     // worry about ?s.
                     Some((data, metadata)) => {
                         let data = strip_hex_prefix(data)?;
-                        let metadata = strip_hex_prefix(metadata)?;
-
-                        // Metadata starts with an extra 0 or 1 for the tag.
-                        let (metadata, tag) = if let Some(metadata) = metadata.strip_prefix('0') {
-                            (metadata, false)
-                        } else if let Some(metadata) = metadata.strip_prefix('1') {
-                            (metadata, true)
-                        } else {
-                            bail!("Invalid metadata, doesn't start 0x1 or 0x0");
-                        };
-                        match size_of::<Usize>() {
+                        let (metadata, tag) = split_tag(metadata)?;
+                        let data = match size_of::<Usize>() {
                             4 => Data::U64(
                                 ((parse_hex::<u32>(metadata)? as u64) << 32)
                                     | parse_hex::<u32>(data)? as u64,
@@ -87,20 +101,22 @@ fn read_line<Usize: Num>(line: &str) -> Result<TraceEvent<Usize>> {
                                     | parse_hex::<u64>(data)? as u128,
                             ),
                             _ => bail!("Unsupport XLEN"),
-                        }
+                        };
+                        (data, Some(tag))
                     }
                     None => {
                         let val = strip_hex_prefix(val)?;
                         let val = val.trim_start_matches('?');
 
-                        match val.len() {
+                        let data = match val.len() {
                             2 => Data::U8(parse_hex(val)?),
                             4 => Data::U16(parse_hex(val)?),
                             8 => Data::U32(parse_hex(val)?),
                             16 => Data::U64(parse_hex(val)?),
                             32 => Data::U128(parse_hex(val)?),
                             _ => bail!("Invalid hex length: {val:?}"),
-                        }
+                        };
+                        (data, None)
                     }
                 });
             } else if let Some(val) = part.strip_prefix("PA:") {
@@ -108,34 +124,54 @@ fn read_line<Usize: Num>(line: &str) -> Result<TraceEvent<Usize>> {
                     bail!("Multiple PAs found");
                 }
                 phys_addr = Some(parse_hex(strip_hex_prefix(val)?)?);
-            } else {
-                for index in 1..32 {
-                    if let Some(val) = part.strip_prefix(&format!("x{index}=")) {
-                        if xwrite.is_some() {
-                            bail!("Multiple X writes found");
-                        }
-                        // We ignore the metadata for register writes because I haven't
-                        // found a way to display it yet.
-                        let value = match val.split_once('+') {
-                            Some((data, _metadata)) => parse_hex(strip_hex_prefix(data)?)?,
-                            None => parse_hex(strip_hex_prefix(val)?)?,
-                        };
-                        xwrite = Some(XRegWrite {
-                            index,
-                            value,
-                            prev_value: None,
-                        });
-                    }
+            } else if part.starts_with("load:") || part.starts_with("x0=") {
+                // Loads don't change the state and writes to x0 are discarded.
+            } else if let Some(write) = VRegWrite::parse(part) {
+                if vwrite.is_some() {
+                    bail!("Multiple V writes found");
+                }
+                vwrite = Some(write?);
+            } else if let Some((index, val)) = xreg_write_token(part) {
+                if xwrite.is_some() {
+                    bail!("Multiple X writes found");
                 }
+                let (value, metadata) = match val.split_once('+') {
+                    Some((data, metadata)) => {
+                        let (metadata, tag) = split_tag(metadata)?;
+                        (
+                            parse_hex(strip_hex_prefix(data)?)?,
+                            Some(CapMetadata {
+                                tag,
+                                bits: parse_hex(metadata)?,
+                            }),
+                        )
+                    }
+                    None => (parse_hex(strip_hex_prefix(val)?)?, None),
+                };
+                xwrite = Some(XRegWrite {
+                    index,
+                    value,
+                    metadata,
+                    prev_value: None,
+                    prev_metadata: None,
+                });
+            } else {
+                unknown.add(part);
             }
         }
     }
 
+    if is_failed_store_conditional(instruction, xwrite.as_ref()) {
+        store_val = None;
+    }
+
     let store = match (store_val, phys_addr) {
-        (Some(value), Some(phys_addr)) => Some(MemWrite {
+        (Some((value, tag)), Some(phys_addr)) => Some(MemWrite {
             phys_addr,
             value,
+            tag,
             prev_value: None,
+            prev_tags: Vec::new(),
         }),
         (None, _) => None,
         (Some(_), None) => bail!("Store without PA"),
@@ -145,42 +181,77 @@ fn read_line<Usize: Num>(line: &str) -> Result<TraceEvent<Usize>> {
         time,
         cycle,
         pc,
-        trap: assembly_mnemonic.is_some_and(|s| s.starts_with("-->")),
+        trap: assembly_mnemonic.is_some_and(has_trap_marker),
         instruction: Some(instruction),
         assembly_mnemonic: assembly_mnemonic.unwrap_or_default().to_owned(),
         assembly_args: assembly_args.unwrap_or_default().to_owned(),
         xwrite,
+        vwrite,
         store,
+        csr_writes: Vec::new(),
+        raw: None,
+        line: 0,
     })
 }
 
-pub fn read_trace<Usize: Num>(file_path: &Path) -> Result<Vec<TraceEvent<Usize>>> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-
-    let mut events = Vec::new();
+pub fn read_trace<Usize: Num>(
+    file_path: &Path,
+    options: &ReadOptions,
+) -> Result<Vec<TraceEvent<Usize>>> {
+    read_trace_file(file_path, options, |line, unknown| {
+        read_line(line, &options.columns, unknown)
+    })
+}
 
-    for (line_number, line) in reader.lines().enumerate() {
-        let line_number_plus_one = line_number + 1;
-        let line = line.with_context(|| {
-            format!(
-                "reading line {}:{line_number_plus_one}",
-                file_path.display()
-            )
-        })?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::fixture;
 
-        if line.starts_with("Time") {
-            // Skip header.
-            continue;
-        }
+    fn summary(event: &TraceEvent<u32>) -> String {
+        format!(
+            "{} {} {:#x} {:x?} {:x?} {:x?}",
+            event.time,
+            event.cycle,
+            event.pc,
+            event.instruction,
+            event
+                .xwrite
+                .as_ref()
+                .map(|w| (w.index, w.value, w.metadata.map(|m| (m.tag, m.bits)))),
+            event
+                .store
+                .as_ref()
+                .map(|s| (s.phys_addr, s.value.to_u128(), s.tag)),
+        )
+    }
 
-        events.push(read_line(&line).with_context(|| {
-            format!(
-                "processing line {}:{line_number_plus_one}",
-                file_path.display()
-            )
-        })?);
+    #[test]
+    fn reordered_columns() {
+        let options = ReadOptions {
+            columns: "pc=0,time=1,cycle=2,insn=3,accesses=4".parse().unwrap(),
+            ..Default::default()
+        };
+        let reordered: Vec<TraceEvent<u32>> =
+            read_trace(&fixture("cheriot_reordered.log"), &options).unwrap();
+        let cheriot: Vec<TraceEvent<u32>> =
+            read_trace(&fixture("captag.log"), &Default::default()).unwrap();
+        assert_eq!(
+            reordered.iter().map(summary).collect::<Vec<_>>(),
+            cheriot.iter().map(summary).collect::<Vec<_>>()
+        );
+        assert_eq!(reordered[0].assembly_mnemonic, "");
     }
 
-    Ok(events)
+    #[test]
+    fn capability_xwrite() {
+        let line = "10\t1\t00000000\t0000a003\tclc\tc10,0(c0)\tx10=0x00000ff0+0x17e3e0000 foo:0x1";
+        let mut unknown = UnknownTokens::default();
+        let event: TraceEvent<u32> = read_line(line, &Default::default(), &mut unknown).unwrap();
+        assert_eq!(
+            summary(&event),
+            "10 1 0x0 Some(a003) Some((a, ff0, Some((true, 7e3e0000)))) None"
+        );
+        assert_eq!(unknown.summary().as_deref(), Some(r#""foo:" x1"#));
+    }
 }
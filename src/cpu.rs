@@ -4,7 +4,7 @@ use num_traits::Num;
 
 use crate::{
     memory::Memory,
-    trace::{Data, TraceEvent},
+    trace::{CapMetadata, Data, TraceEvent},
 };
 
 #[derive(Debug, Default, Clone)]
@@ -22,9 +22,13 @@ pub struct Cpu<Usize: Num> {
     pub privilege: Privilege,
 
     pub xregs: [Usize; 32],
+    // Capability metadata for the X registers. `None` for integers.
+    pub xmeta: [Option<CapMetadata>; 32],
     // TODO: But float registers could be larger.
     pub fregs: [Usize; 32],
-    // TODO: Vector regs.
+    // Vector registers, VLEN/8 bytes each, little endian. Empty if VLEN
+    // isn't known (`--vlen`), in which case vector writes are ignored.
+    pub vregs: Vec<Vec<u8>>,
     pub csrs: HashMap<u16, Usize>,
 }
 
@@ -36,10 +40,27 @@ impl<Usize: Num + Copy> Cpu<Usize> {
         // X register write.
         if let Some(xwrite) = &mut event.xwrite {
             xwrite.prev_value = Some(self.xregs[xwrite.index as usize]);
+            xwrite.prev_metadata = self.xmeta[xwrite.index as usize];
             self.xregs[xwrite.index as usize] = xwrite.value;
+            self.xmeta[xwrite.index as usize] = xwrite.metadata;
         }
 
-        // Memory store.
+        // CSR writes.
+        for write in &mut event.csr_writes {
+            write.prev_value = self.csrs.insert(write.csr, write.value);
+        }
+
+        // V register write.
+        if let Some(vwrite) = &mut event.vwrite
+            && let Some(reg) = self.vregs.get_mut(vwrite.index as usize)
+        {
+            let mut value = vwrite.value.clone();
+            value.resize(reg.len(), 0);
+            vwrite.prev_value = Some(std::mem::replace(reg, value));
+        }
+
+        // Memory store. For AMOs the previous value is the value before the
+        // read-modify-write, which is what reverse needs to restore.
         if let Some(store) = &mut event.store {
             match store.value {
                 Data::U8(val) => {
@@ -63,25 +84,52 @@ impl<Usize: Num + Copy> Cpu<Usize> {
                     mem.w128(store.phys_addr, val);
                 }
             }
+
+            // Storing anything other than a whole capability clears the
+            // tags of the slots it overlaps.
+            let cap_size = 2 * size_of::<Usize>() as u64;
+            let end = store.phys_addr + store.value.size() as u64;
+            let whole_cap = store.phys_addr % cap_size == 0 && end - store.phys_addr == cap_size;
+            store.prev_tags.clear();
+            let first_slot = store.phys_addr - store.phys_addr % cap_size;
+            for slot in (first_slot..end).step_by(cap_size as usize) {
+                let tag = whole_cap && store.tag == Some(true);
+                let prev_tag = mem.tag(slot);
+                if tag != prev_tag {
+                    store.prev_tags.push((slot, prev_tag));
+                    mem.set_tag(slot, tag);
+                }
+            }
         }
     }
 
-    // Undo a step (i.e. step backwards).
-    pub fn step_undo(
-        &mut self,
-        mem: &mut impl Memory,
-        event: &TraceEvent<Usize>,
-        prev_event: Option<&TraceEvent<Usize>>,
-    ) {
-        if let Some(prev_event) = prev_event {
-            self.pc = prev_event.pc;
-        }
+    // Undo a step (i.e. step backwards). `prev_pc` is the PC of the previous
+    // event, or the initial PC when undoing the first one.
+    pub fn step_undo(&mut self, mem: &mut impl Memory, event: &TraceEvent<Usize>, prev_pc: Usize) {
+        self.pc = prev_pc;
 
         // X register write.
         if let Some(xwrite) = &event.xwrite
             && let Some(prev_val) = xwrite.prev_value
         {
             self.xregs[xwrite.index as usize] = prev_val;
+            self.xmeta[xwrite.index as usize] = xwrite.prev_metadata;
+        }
+
+        // CSR writes, in reverse in case the same CSR is written twice.
+        for write in event.csr_writes.iter().rev() {
+            match write.prev_value {
+                Some(prev_val) => self.csrs.insert(write.csr, prev_val),
+                None => self.csrs.remove(&write.csr),
+            };
+        }
+
+        // V register write.
+        if let Some(vwrite) = &event.vwrite
+            && let Some(prev_val) = &vwrite.prev_value
+            && let Some(reg) = self.vregs.get_mut(vwrite.index as usize)
+        {
+            reg.clone_from(prev_val);
         }
 
         // Memory store.
@@ -105,6 +153,10 @@ impl<Usize: Num + Copy> Cpu<Usize> {
                     mem.w128(store.phys_addr, *val);
                 }
             }
+
+            for (slot, tag) in &store.prev_tags {
+                mem.set_tag(*slot, *tag);
+            }
         }
     }
 }
@@ -0,0 +1,367 @@
+//! A minimal instruction decoder for traces that don't include the
+//! disassembly. It only finds the mnemonic, not the operands. Enabled with
+//! `--decode`.
+
+use riscv_opcodes::*;
+
+/// Put in `assembly_args` for decoded instructions so they can't be
+/// mistaken for what the trace said.
+pub const DECODED_ARGS: &str = "(decoded)";
+
+/// (mask, match, mnemonic). Earlier entries take priority, so more specific
+/// encodings (e.g. `c.nop` vs `c.addi`) must come first.
+type Table = &'static [(u32, u32, &'static str)];
+
+const COMMON: Table = &[
+    (MASK_LUI, MATCH_LUI, "lui"),
+    (MASK_AUIPC, MATCH_AUIPC, "auipc"),
+    (MASK_JAL, MATCH_JAL, "jal"),
+    (MASK_JALR, MATCH_JALR, "jalr"),
+    (MASK_BEQ, MATCH_BEQ, "beq"),
+    (MASK_BNE, MATCH_BNE, "bne"),
+    (MASK_BLT, MATCH_BLT, "blt"),
+    (MASK_BGE, MATCH_BGE, "bge"),
+    (MASK_BLTU, MATCH_BLTU, "bltu"),
+    (MASK_BGEU, MATCH_BGEU, "bgeu"),
+    (MASK_LB, MATCH_LB, "lb"),
+    (MASK_LH, MATCH_LH, "lh"),
+    (MASK_LW, MATCH_LW, "lw"),
+    (MASK_LBU, MATCH_LBU, "lbu"),
+    (MASK_LHU, MATCH_LHU, "lhu"),
+    (MASK_SB, MATCH_SB, "sb"),
+    (MASK_SH, MATCH_SH, "sh"),
+    (MASK_SW, MATCH_SW, "sw"),
+    (MASK_ADDI, MATCH_ADDI, "addi"),
+    (MASK_SLTI, MATCH_SLTI, "slti"),
+    (MASK_SLTIU, MATCH_SLTIU, "sltiu"),
+    (MASK_XORI, MATCH_XORI, "xori"),
+    (MASK_ORI, MATCH_ORI, "ori"),
+    (MASK_ANDI, MATCH_ANDI, "andi"),
+    (MASK_SLLI, MATCH_SLLI, "slli"),
+    (MASK_SRLI, MATCH_SRLI, "srli"),
+    (MASK_SRAI, MATCH_SRAI, "srai"),
+    (MASK_ADD, MATCH_ADD, "add"),
+    (MASK_SUB, MATCH_SUB, "sub"),
+    (MASK_SLL, MATCH_SLL, "sll"),
+    (MASK_SLT, MATCH_SLT, "slt"),
+    (MASK_SLTU, MATCH_SLTU, "sltu"),
+    (MASK_XOR, MATCH_XOR, "xor"),
+    (MASK_SRL, MATCH_SRL, "srl"),
+    (MASK_SRA, MATCH_SRA, "sra"),
+    (MASK_OR, MATCH_OR, "or"),
+    (MASK_AND, MATCH_AND, "and"),
+    (MASK_FENCE_I, MATCH_FENCE_I, "fence.i"),
+    (MASK_FENCE, MATCH_FENCE, "fence"),
+    (MASK_ECALL, MATCH_ECALL, "ecall"),
+    (MASK_EBREAK, MATCH_EBREAK, "ebreak"),
+    (MASK_MRET, MATCH_MRET, "mret"),
+    (MASK_SRET, MATCH_SRET, "sret"),
+    (MASK_WFI, MATCH_WFI, "wfi"),
+    (MASK_CSRRW, MATCH_CSRRW, "csrrw"),
+    (MASK_CSRRS, MATCH_CSRRS, "csrrs"),
+    (MASK_CSRRC, MATCH_CSRRC, "csrrc"),
+    (MASK_CSRRWI, MATCH_CSRRWI, "csrrwi"),
+    (MASK_CSRRSI, MATCH_CSRRSI, "csrrsi"),
+    (MASK_CSRRCI, MATCH_CSRRCI, "csrrci"),
+    (MASK_MUL, MATCH_MUL, "mul"),
+    (MASK_MULH, MATCH_MULH, "mulh"),
+    (MASK_MULHSU, MATCH_MULHSU, "mulhsu"),
+    (MASK_MULHU, MATCH_MULHU, "mulhu"),
+    (MASK_DIV, MATCH_DIV, "div"),
+    (MASK_DIVU, MATCH_DIVU, "divu"),
+    (MASK_REM, MATCH_REM, "rem"),
+    (MASK_REMU, MATCH_REMU, "remu"),
+    (MASK_LR_W, MATCH_LR_W, "lr.w"),
+    (MASK_SC_W, MATCH_SC_W, "sc.w"),
+    (MASK_AMOSWAP_W, MATCH_AMOSWAP_W, "amoswap.w"),
+    (MASK_AMOADD_W, MATCH_AMOADD_W, "amoadd.w"),
+    (MASK_AMOXOR_W, MATCH_AMOXOR_W, "amoxor.w"),
+    (MASK_AMOAND_W, MATCH_AMOAND_W, "amoand.w"),
+    (MASK_AMOOR_W, MATCH_AMOOR_W, "amoor.w"),
+    (MASK_AMOMIN_W, MATCH_AMOMIN_W, "amomin.w"),
+    (MASK_AMOMAX_W, MATCH_AMOMAX_W, "amomax.w"),
+    (MASK_AMOMINU_W, MATCH_AMOMINU_W, "amominu.w"),
+    (MASK_AMOMAXU_W, MATCH_AMOMAXU_W, "amomaxu.w"),
+    // Compressed.
+    (MASK_C_ADDI4SPN, MATCH_C_ADDI4SPN, "c.addi4spn"),
+    (MASK_C_LW, MATCH_C_LW, "c.lw"),
+    (MASK_C_SW, MATCH_C_SW, "c.sw"),
+    (MASK_C_NOP, MATCH_C_NOP, "c.nop"),
+    (MASK_C_ADDI, MATCH_C_ADDI, "c.addi"),
+    (MASK_C_LI, MATCH_C_LI, "c.li"),
+    (MASK_C_ADDI16SP, MATCH_C_ADDI16SP, "c.addi16sp"),
+    (MASK_C_LUI, MATCH_C_LUI, "c.lui"),
+    (MASK_C_SRLI, MATCH_C_SRLI, "c.srli"),
+    (MASK_C_SRAI, MATCH_C_SRAI, "c.srai"),
+    (MASK_C_ANDI, MATCH_C_ANDI, "c.andi"),
+    (MASK_C_SUB, MATCH_C_SUB, "c.sub"),
+    (MASK_C_XOR, MATCH_C_XOR, "c.xor"),
+    (MASK_C_OR, MATCH_C_OR, "c.or"),
+    (MASK_C_AND, MATCH_C_AND, "c.and"),
+    (MASK_C_J, MATCH_C_J, "c.j"),
+    (MASK_C_BEQZ, MATCH_C_BEQZ, "c.beqz"),
+    (MASK_C_BNEZ, MATCH_C_BNEZ, "c.bnez"),
+    (MASK_C_SLLI, MATCH_C_SLLI, "c.slli"),
+    (MASK_C_LWSP, MATCH_C_LWSP, "c.lwsp"),
+    (MASK_C_JR, MATCH_C_JR, "c.jr"),
+    (MASK_C_MV, MATCH_C_MV, "c.mv"),
+    (MASK_C_EBREAK, MATCH_C_EBREAK, "c.ebreak"),
+    (MASK_C_JALR, MATCH_C_JALR, "c.jalr"),
+    (MASK_C_ADD, MATCH_C_ADD, "c.add"),
+    (MASK_C_SWSP, MATCH_C_SWSP, "c.swsp"),
+];
+
+const RV32: Table = &[
+    (MASK_C_JAL, MATCH_C_JAL, "c.jal"),
+    (MASK_C_FLW, MATCH_C_FLW, "c.flw"),
+    (MASK_C_FSW, MATCH_C_FSW, "c.fsw"),
+    (MASK_C_FLWSP, MATCH_C_FLWSP, "c.flwsp"),
+    (MASK_C_FSWSP, MATCH_C_FSWSP, "c.fswsp"),
+];
+
+const RV64: Table = &[
+    (MASK_LWU, MATCH_LWU, "lwu"),
+    (MASK_LD, MATCH_LD, "ld"),
+    (MASK_SD, MATCH_SD, "sd"),
+    (MASK_ADDIW, MATCH_ADDIW, "addiw"),
+    (MASK_SLLIW, MATCH_SLLIW, "slliw"),
+    (MASK_SRLIW, MATCH_SRLIW, "srliw"),
+    (MASK_SRAIW, MATCH_SRAIW, "sraiw"),
+    (MASK_ADDW, MATCH_ADDW, "addw"),
+    (MASK_SUBW, MATCH_SUBW, "subw"),
+    (MASK_SLLW, MATCH_SLLW, "sllw"),
+    (MASK_SRLW, MATCH_SRLW, "srlw"),
+    (MASK_SRAW, MATCH_SRAW, "sraw"),
+    (MASK_MULW, MATCH_MULW, "mulw"),
+    (MASK_DIVW, MATCH_DIVW, "divw"),
+    (MASK_DIVUW, MATCH_DIVUW, "divuw"),
+    (MASK_REMW, MATCH_REMW, "remw"),
+    (MASK_REMUW, MATCH_REMUW, "remuw"),
+    (MASK_LR_D, MATCH_LR_D, "lr.d"),
+    (MASK_SC_D, MATCH_SC_D, "sc.d"),
+    (MASK_AMOSWAP_D, MATCH_AMOSWAP_D, "amoswap.d"),
+    (MASK_AMOADD_D, MATCH_AMOADD_D, "amoadd.d"),
+    (MASK_C_ADDIW, MATCH_C_ADDIW, "c.addiw"),
+    (MASK_C_LD, MATCH_C_LD, "c.ld"),
+    (MASK_C_SD, MATCH_C_SD, "c.sd"),
+    (MASK_C_LDSP, MATCH_C_LDSP, "c.ldsp"),
+    (MASK_C_SDSP, MATCH_C_SDSP, "c.sdsp"),
+    (MASK_C_SUBW, MATCH_C_SUBW, "c.subw"),
+    (MASK_C_ADDW, MATCH_C_ADDW, "c.addw"),
+];
+
+/// Length in bytes of an encoded instruction.
+pub fn instruction_length(instruction: u32) -> u64 {
+    if instruction & 0b11 == 0b11 { 4 } else { 2 }
+}
+
+/// Returns a function that checks whether `instruction` matches a
+/// `riscv_opcodes` mask and match pair, e.g.
+/// `is(MASK_JAL, MATCH_JAL)`.
+pub fn matcher(instruction: u32) -> impl Fn(u32, u32) -> bool {
+    move |mask, mtch| instruction & mask == mtch
+}
+
+/// The CSR accessed by a Zicsr instruction (`csrrw`, `csrrs` etc.), or
+/// `None` if it isn't one. The destination register gets the old value of
+/// the CSR.
+pub fn csr_accessed(instruction: u32) -> Option<u16> {
+    let is = matcher(instruction);
+    [
+        (MASK_CSRRW, MATCH_CSRRW),
+        (MASK_CSRRS, MATCH_CSRRS),
+        (MASK_CSRRC, MATCH_CSRRC),
+        (MASK_CSRRWI, MATCH_CSRRWI),
+        (MASK_CSRRSI, MATCH_CSRRSI),
+        (MASK_CSRRCI, MATCH_CSRRCI),
+    ]
+    .into_iter()
+    .any(|(mask, mtch)| is(mask, mtch))
+    .then_some((instruction >> 20) as u16)
+}
+
+/// The mnemonic of `instruction`, or `None` if it isn't recognised.
+pub fn mnemonic(instruction: u32, xlen: usize) -> Option<&'static str> {
+    // Compressed instructions only use the lower 16 bits.
+    let instruction = if instruction_length(instruction) == 4 {
+        instruction
+    } else {
+        instruction & 0xffff
+    };
+    let is = matcher(instruction);
+
+    let xlen_table = if xlen == 32 { RV32 } else { RV64 };
+    xlen_table
+        .iter()
+        .chain(COMMON)
+        .find(|(mask, mtch, _)| is(*mask, *mtch))
+        .map(|(_, _, name)| *name)
+}
+
+/// Sign extend the lowest `width` bits of `value`.
+fn sign_extend(value: u32, width: u32) -> i64 {
+    let shift = 32 - width;
+    (((value << shift) as i32) >> shift) as i64
+}
+
+/// Extract `len` bits of `instruction` starting at bit `lo`.
+fn bits(instruction: u32, lo: u32, len: u32) -> u32 {
+    (instruction >> lo) & ((1 << len) - 1)
+}
+
+/// Offset of a B-type (conditional branch) instruction.
+fn b_imm(insn: u32) -> i64 {
+    let imm = (bits(insn, 31, 1) << 12)
+        | (bits(insn, 7, 1) << 11)
+        | (bits(insn, 25, 6) << 5)
+        | (bits(insn, 8, 4) << 1);
+    sign_extend(imm, 13)
+}
+
+/// Offset of a J-type (`jal`) instruction.
+fn j_imm(insn: u32) -> i64 {
+    let imm = (bits(insn, 31, 1) << 20)
+        | (bits(insn, 12, 8) << 12)
+        | (bits(insn, 20, 1) << 11)
+        | (bits(insn, 21, 10) << 1);
+    sign_extend(imm, 21)
+}
+
+/// Offset of a CB-format branch (`c.beqz`, `c.bnez`).
+fn cb_imm(insn: u32) -> i64 {
+    let imm = (bits(insn, 12, 1) << 8)
+        | (bits(insn, 5, 2) << 6)
+        | (bits(insn, 2, 1) << 5)
+        | (bits(insn, 10, 2) << 3)
+        | (bits(insn, 3, 2) << 1);
+    sign_extend(imm, 9)
+}
+
+/// Offset of a CJ-format jump (`c.j`, `c.jal`).
+fn cj_imm(insn: u32) -> i64 {
+    let imm = (bits(insn, 12, 1) << 11)
+        | (bits(insn, 8, 1) << 10)
+        | (bits(insn, 9, 2) << 8)
+        | (bits(insn, 6, 1) << 7)
+        | (bits(insn, 7, 1) << 6)
+        | (bits(insn, 2, 1) << 5)
+        | (bits(insn, 11, 1) << 4)
+        | (bits(insn, 3, 3) << 1);
+    sign_extend(imm, 12)
+}
+
+/// The target of a direct branch or jump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchTarget {
+    /// An unconditional jump (`jal`, `c.j`, `c.jal`) to this address.
+    Jump(u64),
+    /// A conditional branch to this address, which may not be taken.
+    Branch(u64),
+}
+
+/// The target of `instruction` at `pc` if it is a PC-relative branch or
+/// jump. Indirect jumps (`jalr` etc.) return `None` since the target depends
+/// on a register.
+pub fn branch_target(instruction: u32, pc: u64, xlen: usize) -> Option<BranchTarget> {
+    let is = matcher(instruction);
+    let target = |offset: i64| pc.wrapping_add_signed(offset) & (u64::MAX >> (64 - xlen));
+
+    if instruction_length(instruction) == 4 {
+        if is(MASK_JAL, MATCH_JAL) {
+            Some(BranchTarget::Jump(target(j_imm(instruction))))
+        } else if is(MASK_BEQ, MATCH_BEQ)
+            || is(MASK_BNE, MATCH_BNE)
+            || is(MASK_BLT, MATCH_BLT)
+            || is(MASK_BGE, MATCH_BGE)
+            || is(MASK_BLTU, MATCH_BLTU)
+            || is(MASK_BGEU, MATCH_BGEU)
+        {
+            Some(BranchTarget::Branch(target(b_imm(instruction))))
+        } else {
+            None
+        }
+    } else if is(MASK_C_J, MATCH_C_J)
+        // `c.jal` is `c.addiw` on RV64.
+        || (xlen == 32 && is(MASK_C_JAL, MATCH_C_JAL))
+    {
+        Some(BranchTarget::Jump(target(cj_imm(instruction))))
+    } else if is(MASK_C_BEQZ, MATCH_C_BEQZ) || is(MASK_C_BNEZ, MATCH_C_BNEZ) {
+        Some(BranchTarget::Branch(target(cb_imm(instruction))))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branch_targets() {
+        use BranchTarget::{Branch, Jump};
+
+        for (instruction, pc, target) in [
+            // c.j 8
+            (0xa021, 0x0, Some(Jump(0x8))),
+            // c.j -4
+            (0xbff5, 0x1c, Some(Jump(0x18))),
+            // beq x10,x11,16
+            (0x00b50863, 0x8, Some(Branch(0x18))),
+            // beq x10,x11,-2048, wrapping below 0.
+            (0x80b500e3, 0x18, Some(Branch(0xffff_f818))),
+            // c.beqz x10,-8
+            (0xdd65, 0x30, Some(Branch(0x28))),
+            // jal x1,4096
+            (0x000010ef, 0x28, Some(Jump(0x1028))),
+            // c.jal 8
+            (0x2021, 0x0, Some(Jump(0x8))),
+            // jalr x0,0(x1)
+            (0x00008067, 0x0, None),
+            // c.nop
+            (0x0001, 0x0, None),
+        ] {
+            assert_eq!(
+                branch_target(instruction, pc, 32),
+                target,
+                "{instruction:#x}"
+            );
+        }
+
+        // On RV64 `c.jal` is `c.addiw`, and addresses don't wrap at 32 bits.
+        assert_eq!(branch_target(0x2021, 0x0, 64), None);
+        assert_eq!(
+            branch_target(0x80b500e3, 0x18, 64),
+            Some(Branch(0xffff_ffff_ffff_f818))
+        );
+    }
+
+    #[test]
+    fn mnemonics() {
+        for (instruction, xlen, name) in [
+            (0x1141, 32, Some("c.addi")),
+            (0x4515, 32, Some("c.li")),
+            // The upper bits of a compressed instruction are ignored.
+            (0xffff_c02a, 32, Some("c.swsp")),
+            (0x05d00893, 32, Some("addi")),
+            (0x00000073, 32, Some("ecall")),
+            (0x342022f3, 32, Some("csrrs")),
+            (0x2021, 32, Some("c.jal")),
+            (0x2021, 64, Some("c.addiw")),
+            (0xffff_ffff, 32, None),
+        ] {
+            assert_eq!(mnemonic(instruction, xlen), name, "{instruction:#x}");
+        }
+    }
+
+    #[test]
+    fn lengths_and_csrs() {
+        assert_eq!(instruction_length(0x1141), 2);
+        assert_eq!(instruction_length(0x05d00893), 4);
+
+        // csrrs x5,mcause,x0
+        assert_eq!(csr_accessed(0x342022f3), Some(0x342));
+        // csrrwi x0,mstatus,8
+        assert_eq!(csr_accessed(0x30045073), Some(0x300));
+        assert_eq!(csr_accessed(0x05d00893), None);
+    }
+}
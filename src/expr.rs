@@ -0,0 +1,204 @@
+//! A tiny expression language for conditions on the CPU state, e.g.
+//! `a0 == 3 && sp < 0x80001000`.
+//!
+//! An expression is comparisons joined with `&&` and `||` (`&&` binds
+//! tighter). Operands are register names (`pc`, `a0`, `x10` etc.) or
+//! integers (decimal or `0x` hex). Comparisons are unsigned.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow, bail};
+use num_traits::{Num, ToPrimitive};
+
+use crate::cpu::Cpu;
+use crate::parse::parse_u64;
+use crate::riscv::reg::names::gpr_from_name;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Pc,
+    Gpr(u8),
+    Literal(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// Longer operators first so `<=` isn't parsed as `<`.
+const COMPARE_OPS: [(&str, CompareOp); 6] = [
+    ("==", CompareOp::Eq),
+    ("!=", CompareOp::Ne),
+    ("<=", CompareOp::Le),
+    (">=", CompareOp::Ge),
+    ("<", CompareOp::Lt),
+    (">", CompareOp::Gt),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Comparison {
+    lhs: Operand,
+    op: CompareOp,
+    rhs: Operand,
+}
+
+/// A parsed condition. The outer list is ORed and the inner lists are ANDed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    any: Vec<Vec<Comparison>>,
+    source: String,
+}
+
+impl FromStr for Operand {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s == "pc" {
+            return Ok(Self::Pc);
+        }
+        if let Some(index) = gpr_from_name(s) {
+            return Ok(Self::Gpr(index));
+        }
+        parse_u64(s)
+            .map(Self::Literal)
+            .map_err(|_| anyhow!("expected a register or integer, got {s:?}"))
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        for (token, op) in COMPARE_OPS {
+            if let Some((lhs, rhs)) = s.split_once(token) {
+                return Ok(Self {
+                    lhs: lhs.parse()?,
+                    op,
+                    rhs: rhs.parse()?,
+                });
+            }
+        }
+        bail!("expected a comparison like `a0 == 1`, got {:?}", s.trim());
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let any = s
+            .split("||")
+            .map(|all| all.split("&&").map(str::parse).collect())
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            any,
+            source: s.trim().to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Operand {
+    fn value<Usize: Num + Copy + ToPrimitive>(self, cpu: &Cpu<Usize>) -> u64 {
+        match self {
+            Self::Pc => cpu.pc.to_u64().unwrap_or_default(),
+            Self::Gpr(index) => cpu.xregs[index as usize].to_u64().unwrap_or_default(),
+            Self::Literal(val) => val,
+        }
+    }
+}
+
+impl Comparison {
+    fn eval<Usize: Num + Copy + ToPrimitive>(&self, cpu: &Cpu<Usize>) -> bool {
+        let (lhs, rhs) = (self.lhs.value(cpu), self.rhs.value(cpu));
+        match self.op {
+            CompareOp::Eq => lhs == rhs,
+            CompareOp::Ne => lhs != rhs,
+            CompareOp::Lt => lhs < rhs,
+            CompareOp::Le => lhs <= rhs,
+            CompareOp::Gt => lhs > rhs,
+            CompareOp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl Condition {
+    /// Evaluate the condition against the current CPU state.
+    pub fn eval<Usize: Num + Copy + ToPrimitive>(&self, cpu: &Cpu<Usize>) -> bool {
+        self.any
+            .iter()
+            .any(|all| all.iter().all(|comparison| comparison.eval(cpu)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu(pc: u32, a0: u32, sp: u32) -> Cpu<u32> {
+        let mut cpu = Cpu {
+            pc,
+            ..Default::default()
+        };
+        cpu.xregs[10] = a0;
+        cpu.xregs[2] = sp;
+        cpu
+    }
+
+    fn eval(condition: &str, cpu: &Cpu<u32>) -> bool {
+        condition.parse::<Condition>().unwrap().eval(cpu)
+    }
+
+    #[test]
+    fn comparisons() {
+        let cpu = cpu(0x100, 3, 0x8000_0ff0);
+        assert!(eval("a0 == 3", &cpu));
+        assert!(eval("x10 == 0x3", &cpu));
+        assert!(!eval("a0 != 3", &cpu));
+        assert!(eval("a0 < 4", &cpu));
+        assert!(eval("a0 <= 3", &cpu));
+        assert!(!eval("a0 > 3", &cpu));
+        assert!(eval("a0 >= 3", &cpu));
+        assert!(eval("pc == 256", &cpu));
+        assert!(eval("3 == a0", &cpu));
+        // Unsigned, so "negative" values are large.
+        assert!(eval("sp > 0x80000000", &cpu));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let cpu = cpu(0x100, 3, 0x1000);
+        assert!(eval("a0 == 1 || a0 == 3 && sp == 0x1000", &cpu));
+        assert!(eval("a0 == 3 && sp == 0x1000 || a0 == 1", &cpu));
+        assert!(!eval("a0 == 1 || a0 == 3 && sp == 0", &cpu));
+        assert!(eval("a0 == 3 && pc == 0x100 && sp == 0x1000", &cpu));
+    }
+
+    #[test]
+    fn display_and_errors() {
+        let condition: Condition = "  a0 == 3 && sp < 0x10 ".parse().unwrap();
+        assert_eq!(condition.to_string(), "a0 == 3 && sp < 0x10");
+
+        for (source, error) in [
+            ("a0", "expected a comparison"),
+            ("a0 == foo", "expected a register or integer, got \"foo\""),
+            ("a0 == 1 &&", "expected a comparison"),
+        ] {
+            let err = source.parse::<Condition>().unwrap_err();
+            assert!(err.to_string().contains(error), "{source}: {err}");
+        }
+    }
+}
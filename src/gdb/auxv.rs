@@ -5,16 +5,33 @@ use super::copy_range_to_buf;
 use gdbstub::internal::LeBytes;
 use gdbstub::target;
 use gdbstub::target::TargetResult;
+use goblin::elf::program_header::{PT_LOAD, PT_PHDR};
 use num_traits::{FromPrimitive, Zero};
 
+// There's no MMU so this is arbitrary, but it's what Linux would say.
+const PAGE_SIZE: u64 = 4096;
+
+/// The address of the program headers in memory, if they are loaded.
+fn phdr_vaddr(elf: &goblin::elf::Elf) -> Option<u64> {
+    if let Some(phdr) = elf.program_headers.iter().find(|ph| ph.p_type == PT_PHDR) {
+        return Some(phdr.p_vaddr);
+    }
+    // Otherwise find the segment that contains them.
+    let phoff = elf.header.e_phoff;
+    elf.program_headers
+        .iter()
+        .find(|ph| ph.p_type == PT_LOAD && ph.file_range().contains(&(phoff as usize)))
+        .map(|ph| ph.p_vaddr + (phoff - ph.p_offset))
+}
+
 // Copied from LLVM. There are more but we don't need them.
 const AUXV_AT_NULL: u8 = 0; // End of auxv.
 // const AUXV_AT_IGNORE: u8 = 1;  // Ignore entry.
 // const AUXV_AT_EXECFD: u8 = 2;  // File descriptor of program.
-// const AUXV_AT_PHDR: u8 = 3;    // Program headers.
-// const AUXV_AT_PHENT: u8 = 4;   // Size of program header.
-// const AUXV_AT_PHNUM: u8 = 5;   // Number of program headers.
-// const AUXV_AT_PAGESZ: u8 = 6;  // Page size.
+const AUXV_AT_PHDR: u8 = 3; // Program headers.
+const AUXV_AT_PHENT: u8 = 4; // Size of program header.
+const AUXV_AT_PHNUM: u8 = 5; // Number of program headers.
+const AUXV_AT_PAGESZ: u8 = 6; // Page size.
 // const AUXV_AT_BASE: u8 = 7;    // Interpreter base address.
 // const AUXV_AT_FLAGS: u8 = 8;   // Flags.
 const AUXV_AT_ENTRY: u8 = 9; // Program entry point.
@@ -27,7 +44,7 @@ const AUXV_AT_ENTRY: u8 = 9; // Program entry point.
 fn append_auxv<Usize: FromPrimitive + LeBytes>(auxv: &mut Vec<u8>, typ: u8, val: Usize) {
     let sz = std::mem::size_of::<Usize>();
     let mut bytes = [0; 8];
-    assert!(bytes.len() <= sz);
+    assert!(sz <= bytes.len());
 
     // TODO: I *think* this should be host byte order but it's not totally
     // clear. Well hopefully nobody is mad enough to still be using Big Endian.
@@ -47,12 +64,87 @@ impl<A: RiscvArch> target::ext::auxv::Auxv for Machine<A> {
     /// try to find the entry point and then calculate a load offset of the
     /// process entry point from the ELF entry point. If this process fails
     /// it doesn't load any segments at all.
+    ///
+    /// The program headers are also described so LLDB doesn't have to probe
+    /// for them. `AT_PHDR` is only given if the headers are actually loaded;
+    /// a made up address would make LLDB calculate a bogus load offset.
     fn get_auxv(&self, offset: u64, length: usize, buf: &mut [u8]) -> TargetResult<usize, Self> {
         let mut auxv: Vec<u8> = Vec::new();
 
+        if let Ok(elf) = goblin::elf::Elf::parse(&self.elf)
+            && elf.header.e_phnum > 0
+        {
+            if let Some(phdr) = phdr_vaddr(&elf).and_then(A::Usize::from_u64) {
+                append_auxv(&mut auxv, AUXV_AT_PHDR, phdr);
+            }
+            append_auxv(
+                &mut auxv,
+                AUXV_AT_PHENT,
+                A::Usize::from_u16(elf.header.e_phentsize).unwrap(),
+            );
+            append_auxv(
+                &mut auxv,
+                AUXV_AT_PHNUM,
+                A::Usize::from_u16(elf.header.e_phnum).unwrap(),
+            );
+        }
+        append_auxv(
+            &mut auxv,
+            AUXV_AT_PAGESZ,
+            A::Usize::from_u64(PAGE_SIZE).unwrap(),
+        );
         append_auxv(&mut auxv, AUXV_AT_ENTRY, self.entry);
         append_auxv(&mut auxv, AUXV_AT_NULL, A::Usize::zero());
 
         Ok(copy_range_to_buf(&auxv, offset, length, buf))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use gdbstub::target::ext::auxv::Auxv as _;
+
+    use super::*;
+    use crate::riscv::RiscvArch32;
+    use crate::test_util::machine;
+
+    fn auxv(elf: &str) -> Vec<(u32, u32)> {
+        let machine = machine::<RiscvArch32>(elf, Vec::new(), Default::default());
+        let mut buf = [0; 256];
+        let len = machine.get_auxv(0, buf.len(), &mut buf).ok().unwrap();
+        buf[..len]
+            .chunks(8)
+            .map(|pair| {
+                let word = |i: usize| u32::from_le_bytes(pair[i..i + 4].try_into().unwrap());
+                (word(0), word(4))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn program_headers() {
+        assert_eq!(
+            auxv("segments.elf"),
+            [
+                (AUXV_AT_PHDR as u32, 0x10000 + 256),
+                (AUXV_AT_PHENT as u32, 32),
+                (AUXV_AT_PHNUM as u32, 2),
+                (AUXV_AT_PAGESZ as u32, 4096),
+                (AUXV_AT_ENTRY as u32, 0),
+                (AUXV_AT_NULL as u32, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_program_headers() {
+        assert_eq!(
+            auxv("rv32.elf"),
+            [
+                (AUXV_AT_PAGESZ as u32, 4096),
+                (AUXV_AT_ENTRY as u32, 0x20),
+                (AUXV_AT_NULL as u32, 0),
+            ]
+        );
+    }
+}
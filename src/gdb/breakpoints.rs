@@ -28,7 +28,7 @@ impl<A: RiscvArch> target::ext::breakpoints::SwBreakpoint for Machine<A> {
         addr: A::Usize,
         _kind: <A::BaseArch as Arch>::BreakpointKind,
     ) -> TargetResult<bool, Self> {
-        self.breakpoints.push(addr);
+        self.breakpoints.insert(addr);
         Ok(true)
     }
 
@@ -37,12 +37,7 @@ impl<A: RiscvArch> target::ext::breakpoints::SwBreakpoint for Machine<A> {
         addr: A::Usize,
         _kind: <A::BaseArch as Arch>::BreakpointKind,
     ) -> TargetResult<bool, Self> {
-        match self.breakpoints.iter().position(|x| *x == addr) {
-            None => return Ok(false),
-            Some(pos) => self.breakpoints.remove(pos),
-        };
-
-        Ok(true)
+        Ok(self.breakpoints.remove(&addr))
     }
 }
 
@@ -86,3 +81,37 @@ impl<A: RiscvArch> target::ext::breakpoints::HwWatchpoint for Machine<A> {
         Ok(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use gdbstub::stub::SingleThreadStopReason;
+    use gdbstub::target::ext::breakpoints::SwBreakpoint as _;
+
+    use crate::test_util::trace_machine;
+
+    #[test]
+    fn many_breakpoints() {
+        let mut machine = trace_machine("loop.log", Default::default());
+
+        // 49 that are never hit and one after the loop.
+        for i in 0..49 {
+            assert_eq!(
+                machine.add_sw_breakpoint(0x1000 + 2 * i, 2).ok(),
+                Some(true)
+            );
+        }
+        assert_eq!(machine.add_sw_breakpoint(0xa, 2).ok(), Some(true));
+
+        let stop = loop {
+            if let Some(stop) = machine.step() {
+                break stop;
+            }
+        };
+        assert!(matches!(stop, SingleThreadStopReason::SwBreak(())));
+        assert_eq!(machine.cpu.pc, 0xa);
+
+        assert_eq!(machine.remove_sw_breakpoint(0xa, 2).ok(), Some(true));
+        assert_eq!(machine.remove_sw_breakpoint(0xa, 2).ok(), Some(false));
+        assert_eq!(machine.breakpoints.len(), 49);
+    }
+}
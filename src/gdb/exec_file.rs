@@ -6,8 +6,20 @@ use gdbstub::common::Pid;
 use gdbstub::target;
 use gdbstub::target::TargetResult;
 
-// Fake path for the ELF that is on the target so GDB can remotely access it.
-pub const FAKE_ELF_FILENAME: &[u8; 9] = b"/test.elf";
+// Default fake path for the ELF that is on the target so GDB can remotely
+// access it. Override with `--exec-name`.
+pub const DEFAULT_EXEC_NAME: &str = "/test.elf";
+
+impl<A: RiscvArch> Machine<A> {
+    /// The path the ELF is served at over host I/O.
+    pub fn exec_name(&self) -> &[u8] {
+        self.options
+            .exec_name
+            .as_deref()
+            .unwrap_or(DEFAULT_EXEC_NAME)
+            .as_bytes()
+    }
+}
 
 impl<A: RiscvArch> target::ext::exec_file::ExecFile for Machine<A> {
     fn get_exec_file(
@@ -17,6 +29,84 @@ impl<A: RiscvArch> target::ext::exec_file::ExecFile for Machine<A> {
         length: usize,
         buf: &mut [u8],
     ) -> TargetResult<usize, Self> {
-        Ok(copy_range_to_buf(FAKE_ELF_FILENAME, offset, length, buf))
+        Ok(copy_range_to_buf(self.exec_name(), offset, length, buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gdbstub::target::ext::exec_file::ExecFile as _;
+    use gdbstub::target::ext::host_io::{
+        HostIoOpen as _, HostIoOpenFlags, HostIoOpenMode, HostIoPread as _,
+    };
+
+    use super::*;
+    use crate::machine::MachineOptions;
+    use crate::riscv::RiscvArch32;
+    use crate::test_util::{fixture, machine};
+
+    fn exec_machine(exec_name: Option<&str>) -> Machine<RiscvArch32> {
+        let options = MachineOptions {
+            exec_name: exec_name.map(str::to_owned),
+            ..Default::default()
+        };
+        machine("rv32.elf", Vec::new(), options)
+    }
+
+    fn exec_file(machine: &Machine<RiscvArch32>, offset: u64, length: usize) -> Option<String> {
+        let mut buf = [0; 16];
+        let len = machine.get_exec_file(None, offset, length, &mut buf).ok()?;
+        Some(String::from_utf8(buf[..len].to_vec()).unwrap())
+    }
+
+    /// Open `path` over host I/O and read all of it in small chunks.
+    fn read_over_host_io(machine: &mut Machine<RiscvArch32>, path: &str) -> Option<Vec<u8>> {
+        let fd = machine
+            .open(
+                path.as_bytes(),
+                HostIoOpenFlags::O_RDONLY,
+                HostIoOpenMode::empty(),
+            )
+            .ok()?;
+        let mut data = Vec::new();
+        loop {
+            let mut buf = [0; 64];
+            let n = machine
+                .pread(fd, buf.len(), data.len() as u64, &mut buf)
+                .ok()?;
+            if n == 0 {
+                return Some(data);
+            }
+            data.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn configured_exec_name() {
+        let mut machine = exec_machine(Some("/fw/app.elf"));
+        assert_eq!(exec_file(&machine, 0, 16).as_deref(), Some("/fw/app.elf"));
+        assert_eq!(exec_file(&machine, 4, 3).as_deref(), Some("app"));
+        assert_eq!(exec_file(&machine, 8, 16).as_deref(), Some("elf"));
+        assert_eq!(exec_file(&machine, 11, 16).as_deref(), Some(""));
+
+        let elf = std::fs::read(fixture("rv32.elf")).unwrap();
+        assert_eq!(read_over_host_io(&mut machine, "/fw/app.elf"), Some(elf));
+        // The default name isn't special any more.
+        assert_eq!(read_over_host_io(&mut machine, DEFAULT_EXEC_NAME), None);
+    }
+
+    #[test]
+    fn default_exec_name() {
+        let mut machine = exec_machine(None);
+        assert_eq!(
+            exec_file(&machine, 0, 16).as_deref(),
+            Some(DEFAULT_EXEC_NAME)
+        );
+
+        let elf = std::fs::read(fixture("rv32.elf")).unwrap();
+        assert_eq!(
+            read_over_host_io(&mut machine, DEFAULT_EXEC_NAME),
+            Some(elf)
+        );
     }
 }
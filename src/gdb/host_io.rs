@@ -1,6 +1,5 @@
 use super::copy_range_to_buf;
 use super::copy_to_buf;
-use crate::gdb::exec_file::FAKE_ELF_FILENAME;
 use crate::machine::Machine;
 use crate::riscv::RiscvArch;
 use gdbstub::target;
@@ -15,9 +14,53 @@ use num_traits::{FromPrimitive, ToPrimitive};
 use std::io::Read;
 use std::io::Seek;
 use std::io::Write;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
 
 const FD_RESERVED: u32 = 1;
 
+// gdbstub reports a PID of 1 for single-threaded targets.
+const FAKE_PID: usize = 1;
+
+impl<A: RiscvArch> Machine<A> {
+    /// Map a path from the debugger to a path on the host. If `--host-io-root`
+    /// was given then paths are relative to it and may not escape it.
+    fn host_path(&self, filename: &[u8]) -> HostIoResult<PathBuf, Self> {
+        let path =
+            std::str::from_utf8(filename).map_err(|_| HostIoError::Errno(HostIoErrno::ENOENT))?;
+
+        let Some(root) = &self.options.host_io_root else {
+            return Ok(PathBuf::from(path));
+        };
+
+        // Paths on the target are absolute: `/foo` is `<root>/foo`. Reject
+        // `..` outright rather than relying on canonicalisation, since the
+        // file may not exist yet.
+        let relative = Path::new(path.trim_start_matches('/'));
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            return Err(HostIoError::Errno(HostIoErrno::EACCES));
+        }
+
+        // Symlinks inside the root could still point outside it. The file
+        // may not exist yet (e.g. it is being created) so check the nearest
+        // ancestor that does. A dangling symlink can't be checked, and
+        // creating the file would follow it, so reject that too.
+        let full = root.join(relative);
+        let existing = full
+            .ancestors()
+            .find(|path| path.symlink_metadata().is_ok())
+            .unwrap_or(root);
+        match existing.canonicalize() {
+            Ok(canonical) if canonical.starts_with(root) => Ok(full),
+            _ => Err(HostIoError::Errno(HostIoErrno::EACCES)),
+        }
+    }
+}
+
 impl<A: RiscvArch> target::ext::host_io::HostIo for Machine<A> {
     #[inline(always)]
     fn support_open(&mut self) -> Option<target::ext::host_io::HostIoOpenOps<'_, Self>> {
@@ -75,12 +118,11 @@ impl<A: RiscvArch> target::ext::host_io::HostIoOpen for Machine<A> {
         // `TEST_PROGRAM_ELF` array using `include_bytes!`. As such, we must "spoof" the
         // existence of a real file, which will actually be backed by the in-binary
         // `TEST_PROGRAM_ELF` array.
-        if filename == FAKE_ELF_FILENAME {
+        if filename == self.exec_name() {
             return Ok(0);
         }
 
-        let path =
-            std::str::from_utf8(filename).map_err(|_| HostIoError::Errno(HostIoErrno::ENOENT))?;
+        let path = self.host_path(filename)?;
 
         let mut read = false;
         let mut write = false;
@@ -241,8 +283,7 @@ impl<A: RiscvArch> target::ext::host_io::HostIoFstat for Machine<A> {
 
 impl<A: RiscvArch> target::ext::host_io::HostIoUnlink for Machine<A> {
     fn unlink(&mut self, filename: &[u8]) -> HostIoResult<(), Self> {
-        let path =
-            std::str::from_utf8(filename).map_err(|_| HostIoError::Errno(HostIoErrno::ENOENT))?;
+        let path = self.host_path(filename)?;
         std::fs::remove_file(path)?;
         Ok(())
     }
@@ -252,7 +293,7 @@ impl<A: RiscvArch> target::ext::host_io::HostIoReadlink for Machine<A> {
     fn readlink<'a>(&mut self, filename: &[u8], buf: &mut [u8]) -> HostIoResult<usize, Self> {
         if filename == b"/proc/1/exe" {
             // Support `info proc exe` command
-            return Ok(copy_to_buf(FAKE_ELF_FILENAME, buf));
+            return Ok(copy_to_buf(self.exec_name(), buf));
         } else if filename == b"/proc/1/cwd" {
             // Support `info proc cwd` command
             let cwd = b"/";
@@ -261,8 +302,7 @@ impl<A: RiscvArch> target::ext::host_io::HostIoReadlink for Machine<A> {
             return Err(HostIoError::Errno(HostIoErrno::ENOENT));
         }
 
-        let path =
-            std::str::from_utf8(filename).map_err(|_| HostIoError::Errno(HostIoErrno::ENOENT))?;
+        let path = self.host_path(filename)?;
         let link = std::fs::read_link(path)?;
         let data = link
             .to_str()
@@ -277,7 +317,94 @@ impl<A: RiscvArch> target::ext::host_io::HostIoReadlink for Machine<A> {
 }
 
 impl<A: RiscvArch> target::ext::host_io::HostIoSetfs for Machine<A> {
-    fn setfs(&mut self, _fs: FsKind) -> HostIoResult<(), Self> {
-        Ok(())
+    fn setfs(&mut self, fs: FsKind) -> HostIoResult<(), Self> {
+        // There is only one filesystem, which is the same for the stub and
+        // our single fake process.
+        match fs {
+            FsKind::Stub => Ok(()),
+            FsKind::Pid(pid) if pid.get() == FAKE_PID => Ok(()),
+            FsKind::Pid(_) => Err(HostIoError::Errno(HostIoErrno::EINVAL)),
+        }
+    }
+}
+
+// The tests use symlinks.
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use gdbstub::target::ext::host_io::{HostIoOpen as _, HostIoPread as _};
+
+    use super::*;
+    use crate::machine::MachineOptions;
+    use crate::riscv::RiscvArch32;
+    use crate::test_util::machine;
+
+    /// A machine whose host I/O root is a new directory `root`, next to a
+    /// directory `outside` that it mustn't be able to access.
+    fn machine_with_root(name: &str) -> (Machine<RiscvArch32>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("rtd-host-io-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("root")).unwrap();
+        std::fs::create_dir_all(dir.join("outside")).unwrap();
+        let root = dir.join("root").canonicalize().unwrap();
+
+        let options = MachineOptions {
+            host_io_root: Some(root.clone()),
+            ..Default::default()
+        };
+        (machine("rv32.elf", Vec::new(), options), root)
+    }
+
+    fn is_eacces<T>(result: HostIoResult<T, Machine<RiscvArch32>>) -> bool {
+        matches!(result, Err(HostIoError::Errno(HostIoErrno::EACCES)))
+    }
+
+    #[test]
+    fn read_inside_root() {
+        let (mut machine, root) = machine_with_root("read");
+        std::fs::write(root.join("hello.txt"), "hello").unwrap();
+
+        let fd = machine
+            .open(
+                b"/hello.txt",
+                HostIoOpenFlags::O_RDONLY,
+                HostIoOpenMode::empty(),
+            )
+            .ok()
+            .unwrap();
+        let mut buf = [0; 16];
+        let n = machine.pread(fd, buf.len(), 0, &mut buf).ok().unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    }
+
+    #[test]
+    fn reject_parent_dir() {
+        let (machine, _) = machine_with_root("parent");
+        assert!(is_eacces(machine.host_path(b"/../outside/file")));
+        assert!(is_eacces(machine.host_path(b"/a/../../outside/file")));
+    }
+
+    #[test]
+    fn reject_symlink_escape() {
+        let (mut machine, root) = machine_with_root("symlink");
+        let outside = root.parent().unwrap().join("outside");
+        std::fs::write(outside.join("secret"), "secret").unwrap();
+        symlink(&outside, root.join("link")).unwrap();
+        symlink(outside.join("missing"), root.join("dangling")).unwrap();
+
+        assert!(is_eacces(machine.host_path(b"/link/secret")));
+        // Files that don't exist yet, which open() would create.
+        assert!(is_eacces(machine.host_path(b"/link/newfile")));
+        assert!(is_eacces(machine.host_path(b"/dangling")));
+        assert!(is_eacces(machine.open(
+            b"/link/newfile",
+            HostIoOpenFlags::O_WRONLY | HostIoOpenFlags::O_CREAT,
+            HostIoOpenMode::empty()
+        )));
+        assert!(!outside.join("newfile").exists());
+
+        // New files that really are inside the root are fine.
+        assert!(machine.host_path(b"/newfile").is_ok());
     }
 }
@@ -53,10 +53,8 @@ impl<A: RiscvArch> target::ext::lldb_register_info_override::LldbRegisterInfoOve
                     RiscvRegId::Priv => "Privilege Mode",
                     _ => "Unknown Registers",
                 };
-                let generic = match r {
-                    // TODO
-                    _ => None,
-                };
+                // TODO
+                let generic = None;
                 let reg = Register {
                     name: &name,
                     alt_name: None,
@@ -10,11 +10,12 @@ mod reverse;
 mod single_register_access;
 mod single_thread_range_step;
 mod single_thread_single_step;
+mod target_description_xml_override;
 mod tracepoints;
 
+use crate::machine::ExecDir;
 use crate::machine::ExecMode;
 use crate::machine::Machine;
-use crate::memory::Memory as _;
 use crate::riscv::RiscvArch;
 use gdbstub::arch::Arch;
 use gdbstub::common::Signal;
@@ -24,7 +25,6 @@ use gdbstub::target::TargetError;
 use gdbstub::target::TargetResult;
 use gdbstub::target::ext::base::singlethread::SingleThreadBase;
 use gdbstub::target::ext::base::singlethread::SingleThreadResume;
-use num_traits::FromPrimitive;
 use num_traits::ToPrimitive;
 
 /// Copy all bytes of `data` to `buf`.
@@ -83,11 +83,30 @@ impl<A: RiscvArch> Target for Machine<A> {
     //     Some(self)
     // }
 
+    #[inline(always)]
+    fn support_auxv(&mut self) -> Option<target::ext::auxv::AuxvOps<'_, Self>> {
+        Some(self)
+    }
+
+    #[inline(always)]
+    fn support_exec_file(&mut self) -> Option<target::ext::exec_file::ExecFileOps<'_, Self>> {
+        Some(self)
+    }
+
     #[inline(always)]
     fn support_host_io(&mut self) -> Option<target::ext::host_io::HostIoOps<'_, Self>> {
         Some(self)
     }
 
+    #[inline(always)]
+    fn support_target_description_xml_override(
+        &mut self,
+    ) -> Option<
+        target::ext::target_description_xml_override::TargetDescriptionXmlOverrideOps<'_, Self>,
+    > {
+        Some(self)
+    }
+
     #[inline(always)]
     fn support_tracepoints(
         &mut self,
@@ -108,9 +127,8 @@ impl<A: RiscvArch> SingleThreadBase for Machine<A> {
             .map(|frame| frame.snapshot.clone())
             .unwrap_or_else(|| self.cpu.clone());
 
-        todo!();
-        // regs.pc = cpu.pc;
-        // regs.x = cpu.xregs;
+        regs.pc = cpu.pc;
+        regs.x = cpu.xregs;
 
         Ok(())
     }
@@ -139,18 +157,28 @@ impl<A: RiscvArch> SingleThreadBase for Machine<A> {
             return Ok(0);
         }
 
-        let mut addr = start_addr;
+        self.mem
+            .read_patched_into(start_addr.to_u64().unwrap(), data);
+
+        // The data doesn't include tags so they are available separately.
+        self.last_read_tags = Some(self.read_tags(start_addr.to_u64().unwrap(), data.len() as u64));
 
-        for val in data.iter_mut() {
-            *val = self.mem.r8(addr.to_u64().unwrap());
-            addr += A::Usize::from_u32(1).unwrap();
-        }
         Ok(data.len())
     }
 
-    fn write_addrs(&mut self, _start_addr: A::Usize, _data: &[u8]) -> TargetResult<(), Self> {
-        // Can't modify memory.
-        Err(TargetError::NonFatal)
+    fn write_addrs(&mut self, start_addr: A::Usize, data: &[u8]) -> TargetResult<(), Self> {
+        // Memory can only be modified if explicitly allowed, and then only
+        // via the overlay so the trace replay isn't affected.
+        if !self.options.allow_writes || self.selected_frame.is_some() {
+            return Err(TargetError::NonFatal);
+        }
+
+        let start_addr = start_addr.to_u64().unwrap();
+
+        for (i, val) in data.iter().enumerate() {
+            self.mem.patch(start_addr + i as u64, *val);
+        }
+        Ok(())
     }
 
     #[inline(always)]
@@ -173,6 +201,9 @@ impl<A: RiscvArch> SingleThreadResume for Machine<A> {
         }
 
         self.exec_mode = ExecMode::Continue;
+        // Reverse continue/step set this but there is nothing to reset it
+        // before continuing forwards.
+        self.exec_dir = ExecDir::Forwards;
 
         Ok(())
     }
@@ -205,3 +236,93 @@ impl<A: RiscvArch> SingleThreadResume for Machine<A> {
         Some(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::machine::MachineOptions;
+    use crate::memory::Memory as _;
+    use crate::riscv::RiscvArch32;
+    use crate::test_util::trace_machine;
+
+    fn read_byte(machine: &mut Machine<RiscvArch32>, addr: u32) -> u8 {
+        let mut buf = [0];
+        assert_eq!(machine.read_addrs(addr, &mut buf).ok(), Some(1));
+        buf[0]
+    }
+
+    #[test]
+    fn writes_need_allow_writes() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        assert!(machine.write_addrs(0xff0, &[0xaa]).is_err());
+        assert_eq!(read_byte(&mut machine, 0xff0), 0);
+    }
+
+    #[test]
+    fn write_overlay() {
+        let options = MachineOptions {
+            allow_writes: true,
+            ..Default::default()
+        };
+        let mut machine = trace_machine("loop.log", options);
+        assert!(machine.write_addrs(0xff0, &[0xaa]).is_ok());
+        assert_eq!(read_byte(&mut machine, 0xff0), 0xaa);
+
+        // The trace stores 5 there. The patch still shadows it but the
+        // replay sees the real value, in both directions.
+        machine.seek(3);
+        assert_eq!(read_byte(&mut machine, 0xff0), 0xaa);
+        assert_eq!(machine.mem.r8(0xff0), 5);
+        machine.seek(0);
+        assert_eq!(read_byte(&mut machine, 0xff0), 0xaa);
+        assert_eq!(machine.mem.r8(0xff0), 0);
+
+        machine.seek(3);
+        assert_eq!(machine.mem.clear_overlay(), 1);
+        assert_eq!(read_byte(&mut machine, 0xff0), 5);
+    }
+
+    #[test]
+    fn reverse_continue_to_start() {
+        use gdbstub::stub::SingleThreadStopReason;
+        use gdbstub::target::ext::base::reverse_exec::ReplayLogPosition;
+
+        let mut machine = trace_machine("loop.log", Default::default());
+        machine.seek(7);
+        machine.exec_dir = ExecDir::Backwards;
+
+        let mut steps = 0;
+        let stop = loop {
+            if let Some(stop) = machine.step() {
+                break stop;
+            }
+            steps += 1;
+        };
+        // Every event is undone before the one stop at the start.
+        assert_eq!(steps, 7);
+        assert!(matches!(
+            stop,
+            SingleThreadStopReason::ReplayLog {
+                pos: ReplayLogPosition::Begin,
+                ..
+            }
+        ));
+        assert_eq!(machine.trace_index, 0);
+        assert_eq!(machine.cpu.pc, 0x0);
+
+        // Going backwards again doesn't move.
+        assert!(matches!(
+            machine.step(),
+            Some(SingleThreadStopReason::ReplayLog {
+                pos: ReplayLogPosition::Begin,
+                ..
+            })
+        ));
+        assert_eq!(machine.trace_index, 0);
+
+        // Continuing forwards moves off the start again.
+        assert!(machine.resume(None).is_ok());
+        assert!(machine.step().is_none());
+        assert_eq!(machine.trace_index, 1);
+    }
+}
@@ -1,8 +1,62 @@
+use crate::capability::decode_capability;
+use crate::expr::Condition;
 use crate::gdb::Machine;
+use crate::machine::ReadTags;
+use crate::parse::parse_u64;
 use crate::riscv::RiscvArch;
+use crate::riscv::reg::names::{GPR_ABI_NAMES, csr_from_name, csr_name, gpr_from_name};
+use crate::trace::TraceEvent;
 use gdbstub::target;
 use gdbstub::target::ext::monitor_cmd::ConsoleOutput;
 use gdbstub::target::ext::monitor_cmd::outputln;
+use num_traits::{FromPrimitive as _, ToPrimitive as _};
+use std::fmt;
+use std::path::Path;
+
+/// Describe the RISC-V specific ELF `e_flags`.
+fn riscv_elf_flags(flags: u32) -> String {
+    const EF_RISCV_RVC: u32 = 0x1;
+    const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+    const EF_RISCV_RVE: u32 = 0x8;
+    const EF_RISCV_TSO: u32 = 0x10;
+
+    let mut parts = vec![match flags & EF_RISCV_FLOAT_ABI {
+        0x0 => "soft float ABI",
+        0x2 => "single float ABI",
+        0x4 => "double float ABI",
+        _ => "quad float ABI",
+    }];
+    if flags & EF_RISCV_RVC != 0 {
+        parts.push("RVC");
+    }
+    if flags & EF_RISCV_RVE != 0 {
+        parts.push("RVE");
+    }
+    if flags & EF_RISCV_TSO != 0 {
+        parts.push("TSO");
+    }
+    parts.join(", ")
+}
+
+fn output_read_tags(out: &mut impl fmt::Write, tags: &ReadTags) {
+    outputln!(
+        out,
+        "[{:#x}, {:#x}): {} tagged slot(s)",
+        tags.addr,
+        tags.addr.saturating_add(tags.len),
+        tags.slots.len()
+    );
+    for (slot, whole) in &tags.slots {
+        if *whole {
+            outputln!(out, "{slot:#x}: tagged");
+        } else {
+            outputln!(
+                out,
+                "{slot:#x}: tagged, but only partly read so not a capability"
+            );
+        }
+    }
+}
 
 // See https://sourceware.org/gdb/current/onlinedocs/gdb.html/Server.html
 // I don't think we really need this.
@@ -12,20 +66,597 @@ impl<A: RiscvArch> target::ext::monitor_cmd::MonitorCmd for Machine<A> {
         cmd: &[u8],
         mut out: ConsoleOutput<'_>,
     ) -> Result<(), Self::Error> {
-        let cmd = match core::str::from_utf8(cmd) {
-            Ok(cmd) => cmd,
-            Err(_) => {
-                outputln!(out, "command must be valid UTF-8");
-                return Ok(());
+        match core::str::from_utf8(cmd) {
+            Ok(cmd) => self.monitor_command(cmd, &mut out),
+            Err(_) => outputln!(out, "command must be valid UTF-8"),
+        }
+        Ok(())
+    }
+}
+
+impl<A: RiscvArch> Machine<A> {
+    /// Run a monitor command, writing its output to `out`. This is also
+    /// used to run `--script` files.
+    pub fn monitor_command(&mut self, cmd: &str, out: &mut impl fmt::Write) {
+        let mut words = cmd.split_ascii_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args: Vec<&str> = words.collect();
+
+        match (name, args.as_slice()) {
+            ("", []) => outputln!(out, "Sorry, didn't catch that. Try `monitor ping`!"),
+            ("ping", []) => outputln!(out, "pong!"),
+            ("clear-overlay", []) => {
+                let n = self.mem.clear_overlay();
+                outputln!(out, "Discarded {n} patched byte(s).")
+            }
+            ("save-state", [path]) => match self.save_state(Path::new(path)) {
+                Ok(()) => outputln!(out, "Saved state at index {} to {path}", self.trace_index),
+                Err(e) => outputln!(out, "Error saving state: {e:#}"),
+            },
+            ("load-state", [path]) => match self.load_state(Path::new(path)) {
+                Ok(()) => outputln!(out, "Loaded state at index {}", self.trace_index),
+                Err(e) => outputln!(out, "Error loading state: {e:#}"),
+            },
+            ("check-regs", [path]) => match self.check_regs(Path::new(path)) {
+                Ok(mismatches) if mismatches.is_empty() => outputln!(out, "OK"),
+                Ok(mismatches) => {
+                    for mismatch in mismatches {
+                        outputln!(out, "{mismatch}");
+                    }
+                }
+                Err(e) => outputln!(out, "Error checking registers: {e:#}"),
+            },
+            ("watch-reg", [name]) => match gpr_from_name(name) {
+                Some(index) => {
+                    self.watched_regs.insert(index);
+                    outputln!(out, "Watching writes to {}", GPR_ABI_NAMES[index as usize]);
+                }
+                None => outputln!(out, "Unknown register '{name}'"),
+            },
+            ("unwatch-reg", [name]) => match gpr_from_name(name) {
+                Some(index) if self.watched_regs.remove(&index) => {
+                    outputln!(out, "No longer watching {}", GPR_ABI_NAMES[index as usize]);
+                }
+                Some(_) => outputln!(out, "'{name}' isn't being watched"),
+                None => outputln!(out, "Unknown register '{name}'"),
+            },
+            ("cap", [name]) => match gpr_from_name(name) {
+                Some(index) => {
+                    let name = GPR_ABI_NAMES[index as usize];
+                    let value = self.cpu.xregs[index as usize].to_u64().unwrap_or_default();
+                    match self.cpu.xmeta[index as usize] {
+                        // CHERIoT is the only RV32 CHERI encoding we know.
+                        Some(meta) if size_of::<A::Usize>() == 4 => {
+                            let fields = decode_capability(meta.bits, value);
+                            let tag = if meta.tag { "valid" } else { "untagged" };
+                            outputln!(out, "{name}: {tag} {fields}");
+                        }
+                        Some(meta) => outputln!(
+                            out,
+                            "{name}: {value:#x} metadata {:#x} tag {}",
+                            meta.bits,
+                            meta.tag as u8
+                        ),
+                        None => outputln!(out, "{name}: {value:#x} (no capability metadata)"),
+                    }
+                }
+                None => outputln!(out, "Unknown register '{name}'"),
+            },
+            ("seek", [offset]) => {
+                // `+N` and `-N` are relative to the current position, and
+                // plain `N` is absolute.
+                let target = if offset.starts_with(['+', '-']) {
+                    offset.parse::<i64>().ok().map(|delta| {
+                        (self.trace_index as i64).saturating_add(delta).max(0) as usize
+                    })
+                } else {
+                    offset.parse::<usize>().ok()
+                };
+                match target {
+                    Some(target) => {
+                        self.selected_frame = None;
+                        let index = self.seek(target);
+                        outputln!(out, "At index {index} of {}", self.trace.len());
+                    }
+                    None => outputln!(out, "Invalid offset '{offset}'"),
+                }
+            }
+            ("goto-insn", [index]) => match index.parse::<i64>() {
+                Ok(requested) => {
+                    let clamped = requested.clamp(0, self.trace.len() as i64);
+                    if clamped != requested {
+                        outputln!(
+                            out,
+                            "Warning: {requested} is outside the trace (0..={}), clamping",
+                            self.trace.len()
+                        );
+                    }
+                    self.selected_frame = None;
+                    let index = self.seek(clamped as usize);
+                    let pc = self.cpu.pc.to_u64().unwrap_or_default();
+                    match self.current_event() {
+                        Some(event) => outputln!(
+                            out,
+                            "At index {index}: pc {pc:#x}, cycle {}, time {}",
+                            event.cycle,
+                            event.time
+                        ),
+                        None => outputln!(out, "At index {index}: pc {pc:#x} (start of trace)"),
+                    }
+                }
+                Err(_) => outputln!(out, "Invalid instruction index '{index}'"),
+            },
+            ("bcond", [addr_str, condition @ ..]) => {
+                let Some((addr, usize_addr)) = parse_u64(addr_str)
+                    .ok()
+                    .and_then(|a| Some((a, A::Usize::from_u64(a)?)))
+                else {
+                    outputln!(out, "Invalid address '{addr_str}'");
+                    return;
+                };
+                if condition.is_empty() {
+                    match self.breakpoint_conditions.remove(&usize_addr) {
+                        Some(_) => outputln!(out, "Removed condition at {addr:#x}"),
+                        None => outputln!(out, "No condition at {addr:#x}"),
+                    }
+                } else {
+                    match condition.join(" ").parse::<Condition>() {
+                        Ok(condition) => {
+                            outputln!(out, "Breakpoint at {addr:#x} stops when {condition}");
+                            self.breakpoint_conditions.insert(usize_addr, condition);
+                        }
+                        Err(e) => outputln!(out, "Invalid condition: {e:#}"),
+                    }
+                }
+            }
+            ("ignore", [addr_str, count_str]) => {
+                let Some((addr, usize_addr)) = parse_u64(addr_str)
+                    .ok()
+                    .and_then(|a| Some((a, A::Usize::from_u64(a)?)))
+                else {
+                    outputln!(out, "Invalid address '{addr_str}'");
+                    return;
+                };
+                match parse_u64(count_str).ok() {
+                    Some(0) => {
+                        self.breakpoint_ignore_counts.remove(&usize_addr);
+                        outputln!(out, "Breakpoint at {addr:#x} will stop next time it is hit");
+                    }
+                    Some(count) => {
+                        self.breakpoint_ignore_counts.insert(usize_addr, count);
+                        outputln!(
+                            out,
+                            "Will ignore the next {count} hit(s) of the breakpoint at {addr:#x}"
+                        );
+                    }
+                    None => outputln!(out, "Invalid count '{count_str}'"),
+                }
             }
+            ("until", [addr_str, count @ ..]) if count.len() <= 1 => {
+                let addr = parse_u64(addr_str).ok();
+                let count = match count {
+                    [count] => count.parse::<usize>().ok().filter(|c| *c > 0),
+                    _ => Some(1),
+                };
+                let (Some(addr), Some(count)) = (addr, count) else {
+                    outputln!(out, "Usage: until <addr> [count]");
+                    return;
+                };
+                // Scanning the trace is much faster than stepping through
+                // it, and doesn't need a breakpoint.
+                let found = self.trace[self.trace_index..]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, event)| event.pc.to_u64() == Some(addr))
+                    .nth(count - 1)
+                    .map(|(offset, _)| self.trace_index + offset);
+                self.selected_frame = None;
+                match found {
+                    Some(index) => {
+                        let index = self.seek(index + 1);
+                        outputln!(out, "Reached {addr:#x} at index {index}");
+                    }
+                    None => {
+                        let index = self.seek(self.trace.len());
+                        outputln!(
+                            out,
+                            "{addr:#x} not reached; at end of trace (index {index})"
+                        );
+                    }
+                }
+            }
+            ("export-image", [path, range @ ..]) => {
+                let range = match range {
+                    [] => Ok(None),
+                    ["--range", range] => range
+                        .split_once(':')
+                        .and_then(|(start, end)| Some(parse_u64(start).ok()?..parse_u64(end).ok()?))
+                        .map(Some)
+                        .ok_or(()),
+                    _ => Err(()),
+                };
+                match range {
+                    Ok(range) => match self.export_image(Path::new(path), range) {
+                        Ok((segments, bytes)) => outputln!(
+                            out,
+                            "Wrote {bytes} byte(s) in {segments} segment(s) to {path}"
+                        ),
+                        Err(e) => outputln!(out, "Error exporting image: {e:#}"),
+                    },
+                    Err(()) => outputln!(out, "Usage: export-image <path> [--range <start>:<end>]"),
+                }
+            }
+            ("csrs", []) => {
+                for (csr, value) in self.known_csrs() {
+                    outputln!(
+                        out,
+                        "{:<14}{csr:#05x}  {:#x}",
+                        csr_name(csr).unwrap_or("?"),
+                        value.to_u64().unwrap_or_default()
+                    );
+                }
+            }
+            ("csr", [name]) => {
+                match csr_from_name(name).or_else(|| parse_u64(name).ok()?.try_into().ok()) {
+                    Some(csr) if csr < 0x1000 => match self.read_csr(csr) {
+                        Some(value) => outputln!(
+                            out,
+                            "{} ({csr:#05x}) = {:#x}",
+                            csr_name(csr).unwrap_or(name),
+                            value.to_u64().unwrap_or_default()
+                        ),
+                        None => outputln!(
+                            out,
+                            "{} ({csr:#05x}) hasn't been written in the trace yet",
+                            csr_name(csr).unwrap_or(name)
+                        ),
+                    },
+                    _ => outputln!(out, "Unknown CSR '{name}'"),
+                }
+            }
+            ("tags", [addr_str, len_str]) => {
+                match (parse_u64(addr_str).ok(), parse_u64(len_str).ok()) {
+                    (Some(addr), Some(len)) => {
+                        let tags = self.read_tags(addr, len);
+                        output_read_tags(out, &tags);
+                    }
+                    _ => outputln!(out, "Usage: tags [<addr> <len>]"),
+                }
+            }
+            ("tags", []) => match &self.last_read_tags {
+                Some(tags) => output_read_tags(out, tags),
+                None => outputln!(out, "No memory has been read yet"),
+            },
+            ("access-log", [addr_str]) => {
+                let Some(addr) = parse_u64(addr_str).ok() else {
+                    outputln!(out, "Invalid address '{addr_str}'");
+                    return;
+                };
+                if self.options.record_accesses.is_none() {
+                    outputln!(out, "Stores aren't being recorded. Use --record-accesses.");
+                    return;
+                }
+                // Only the past; going backwards doesn't remove records.
+                let records: Vec<_> = self
+                    .access_log
+                    .iter()
+                    .filter(|r| r.index < self.trace_index)
+                    .filter(|r| r.addr <= addr && addr < r.addr + r.size as u64)
+                    .collect();
+                if self.access_log_truncated {
+                    outputln!(out, "(The oldest stores have been dropped from the log)");
+                }
+                if records.is_empty() {
+                    outputln!(out, "No recorded stores to {addr:#x}");
+                }
+                for r in records {
+                    outputln!(
+                        out,
+                        "event {} (cycle {}, pc {:#x}): {}-byte store of {:#x} to {:#x}",
+                        r.index,
+                        r.cycle,
+                        r.pc,
+                        r.size,
+                        r.value,
+                        r.addr
+                    );
+                }
+            }
+            ("where", []) => match self.current_event() {
+                Some(event) => outputln!(
+                    out,
+                    "Event {} of {} (trace line {}): pc {:#x}, cycle {}, time {}: {} {}",
+                    self.trace_index - 1,
+                    self.trace.len(),
+                    event.line,
+                    event.pc.to_u64().unwrap_or_default(),
+                    event.cycle,
+                    event.time,
+                    event.assembly_mnemonic,
+                    event.assembly_args
+                ),
+                None => outputln!(out, "At the start of the trace"),
+            },
+            ("isa", []) => {
+                outputln!(
+                    out,
+                    "Arch: {} (XLEN {}, {})",
+                    A::NAME,
+                    size_of::<A::Usize>() * 8,
+                    if A::CHERI { "CHERI" } else { "no CHERI" }
+                );
+                outputln!(out, "Target description: {}", A::TARGET_XML);
+                outputln!(out, "Trace format: {}", self.options.trace_format);
+                match goblin::elf::Elf::parse_header(&self.elf) {
+                    Ok(header) => outputln!(
+                        out,
+                        "ELF: {}-bit, e_flags {:#x} ({})",
+                        if header.e_ident[goblin::elf::header::EI_CLASS]
+                            == goblin::elf::header::ELFCLASS64
+                        {
+                            64
+                        } else {
+                            32
+                        },
+                        header.e_flags,
+                        riscv_elf_flags(header.e_flags)
+                    ),
+                    Err(e) => outputln!(out, "ELF: couldn't parse the header: {e}"),
+                }
+            }
+            ("rawline", []) => match self.current_event() {
+                Some(TraceEvent { raw: Some(raw), .. }) => outputln!(out, "{raw}"),
+                Some(_) => outputln!(out, "Raw lines weren't kept; use --keep-raw"),
+                None => outputln!(out, "No event has been executed yet"),
+            },
+            _ => outputln!(out, "I don't know how to handle '{}'", cmd),
         };
+    }
+}
 
-        match cmd {
-            "" => outputln!(out, "Sorry, didn't catch that. Try `monitor ping`!"),
-            "ping" => outputln!(out, "pong!"),
-            _ => outputln!(out, "I don't know how to handle '{}'", cmd),
+#[cfg(test)]
+mod tests {
+    use gdbstub::stub::SingleThreadStopReason;
+
+    use super::*;
+    use crate::ibex_trace;
+    use crate::memory::Memory as _;
+    use crate::riscv::{RiscvArch32, RiscvArch64};
+    use crate::test_util::{cheriot_trace_machine, fixture, machine, trace_machine};
+    use crate::trace::ReadOptions;
+
+    fn step_until_stop(machine: &mut Machine<RiscvArch32>) -> SingleThreadStopReason<u32> {
+        loop {
+            if let Some(stop) = machine.step() {
+                return stop;
+            }
+        }
+    }
+
+    fn monitor(machine: &mut Machine<RiscvArch32>, cmd: &str) -> String {
+        let mut out = String::new();
+        machine.monitor_command(cmd, &mut out);
+        out
+    }
+
+    #[test]
+    fn seek() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        assert_eq!(monitor(&mut machine, "seek 4"), "At index 4 of 15\n");
+        assert_eq!(monitor(&mut machine, "seek +3"), "At index 7 of 15\n");
+        assert_eq!(monitor(&mut machine, "seek -5"), "At index 2 of 15\n");
+        assert_eq!(machine.trace_index, 2);
+        assert_eq!(machine.cpu.xregs[10], 5);
+
+        // Clamped at both ends.
+        assert_eq!(monitor(&mut machine, "seek -100"), "At index 0 of 15\n");
+        assert_eq!(monitor(&mut machine, "seek +100"), "At index 15 of 15\n");
+        assert_eq!(monitor(&mut machine, "seek 100"), "At index 15 of 15\n");
+
+        assert_eq!(monitor(&mut machine, "seek x"), "Invalid offset 'x'\n");
+    }
+
+    #[test]
+    fn goto_insn() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        assert_eq!(
+            monitor(&mut machine, "goto-insn 3"),
+            "At index 3: pc 0x4, cycle 3, time 30\n"
+        );
+        assert_eq!(machine.mem.r8(0xff0), 5);
+        assert_eq!(
+            monitor(&mut machine, "goto-insn 6"),
+            "At index 6: pc 0x6, cycle 6, time 60\n"
+        );
+        assert_eq!(machine.cpu.xregs[10], 3);
+        assert_eq!(
+            monitor(&mut machine, "goto-insn 1"),
+            "At index 1: pc 0x0, cycle 1, time 10\n"
+        );
+        assert_eq!(machine.cpu.xregs[10], 0);
+        assert_eq!(machine.mem.r8(0xff0), 0);
+
+        assert_eq!(
+            monitor(&mut machine, "goto-insn -1"),
+            "Warning: -1 is outside the trace (0..=15), clamping\nAt index 0: pc 0x0 (start of trace)\n"
+        );
+        assert_eq!(
+            monitor(&mut machine, "goto-insn 20"),
+            "Warning: 20 is outside the trace (0..=15), clamping\nAt index 15: pc 0xe, cycle 15, time 150\n"
+        );
+    }
+
+    #[test]
+    fn bcond() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        machine.breakpoints.insert(0x8);
+        assert_eq!(
+            monitor(&mut machine, "bcond 0x8 a0 == 2"),
+            "Breakpoint at 0x8 stops when a0 == 2\n"
+        );
+
+        // Only the third time round the loop.
+        let stop = step_until_stop(&mut machine);
+        assert!(matches!(stop, SingleThreadStopReason::SwBreak(())));
+        assert_eq!(machine.trace_index, 9);
+        assert_eq!(machine.cpu.xregs[10], 2);
+
+        assert_eq!(
+            monitor(&mut machine, "bcond 0x8"),
+            "Removed condition at 0x8\n"
+        );
+        assert_eq!(monitor(&mut machine, "bcond 0x8"), "No condition at 0x8\n");
+        assert!(
+            monitor(&mut machine, "bcond 0x8 a0 = 1")
+                .starts_with("Invalid condition: expected a comparison")
+        );
+    }
+
+    #[test]
+    fn until() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        // The third time round the loop.
+        assert_eq!(
+            monitor(&mut machine, "until 0x8 3"),
+            "Reached 0x8 at index 9\n"
+        );
+        assert_eq!(machine.cpu.pc, 0x8);
+        assert_eq!(machine.cpu.xregs[10], 2);
+
+        // The count starts from the current position.
+        assert_eq!(
+            monitor(&mut machine, "until 0x8"),
+            "Reached 0x8 at index 11\n"
+        );
+        assert_eq!(machine.cpu.xregs[10], 1);
+
+        assert_eq!(
+            monitor(&mut machine, "until 0x8 5"),
+            "0x8 not reached; at end of trace (index 15)\n"
+        );
+        assert_eq!(
+            monitor(&mut machine, "until 0x8 0"),
+            "Usage: until <addr> [count]\n"
+        );
+    }
+
+    #[test]
+    fn csrs() {
+        let mut machine = cheriot_trace_machine("cheri_bounds.log", Default::default());
+        assert_eq!(
+            monitor(&mut machine, "csr mcause"),
+            "mcause (0x342) hasn't been written in the trace yet\n"
+        );
+
+        // The trap writes mcause and mtval.
+        machine.seek(2);
+        assert_eq!(
+            monitor(&mut machine, "csr mcause"),
+            "mcause (0x342) = 0x1c\n"
+        );
+        assert_eq!(monitor(&mut machine, "csr 0x343"), "mtval (0x343) = 0x1\n");
+        assert_eq!(monitor(&mut machine, "csr cycle"), "cycle (0xc00) = 0x2\n");
+        assert_eq!(monitor(&mut machine, "csr nope"), "Unknown CSR 'nope'\n");
+
+        let csrs = monitor(&mut machine, "csrs");
+        let names: Vec<_> = csrs
+            .lines()
+            .map(|line| line.split_whitespace().next().unwrap())
+            .collect();
+        assert!(
+            names.contains(&"mcause") && names.contains(&"mtval"),
+            "{csrs}"
+        );
+        assert!(csrs.contains("mcause        0x342  0x1c\n"), "{csrs}");
+    }
+
+    #[test]
+    fn where_shows_trace_line() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        assert_eq!(
+            monitor(&mut machine, "where"),
+            "At the start of the trace\n"
+        );
+        machine.seek(3);
+        assert_eq!(
+            monitor(&mut machine, "where"),
+            "Event 2 of 15 (trace line 4): pc 0x4, cycle 3, time 30: c.sw x10,0(x2)\n"
+        );
+    }
+
+    #[test]
+    fn ignore() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        machine.breakpoints.insert(0x8);
+        assert_eq!(
+            monitor(&mut machine, "ignore 0x8 2"),
+            "Will ignore the next 2 hit(s) of the breakpoint at 0x8\n"
+        );
+
+        // The first two times round the loop are skipped.
+        let stop = step_until_stop(&mut machine);
+        assert!(matches!(stop, SingleThreadStopReason::SwBreak(())));
+        assert_eq!(machine.trace_index, 9);
+
+        // After that it stops every time.
+        step_until_stop(&mut machine);
+        assert_eq!(machine.trace_index, 11);
+
+        monitor(&mut machine, "ignore 0x8 5");
+        assert_eq!(
+            monitor(&mut machine, "ignore 0x8 0"),
+            "Breakpoint at 0x8 will stop next time it is hit\n"
+        );
+        step_until_stop(&mut machine);
+        assert_eq!(machine.trace_index, 13);
+
+        assert_eq!(monitor(&mut machine, "ignore 0x8 x"), "Invalid count 'x'\n");
+    }
+
+    #[test]
+    fn isa() {
+        let mut rv32 = trace_machine("loop.log", Default::default());
+        let isa = monitor(&mut rv32, "isa");
+        assert!(isa.starts_with("Arch: RV32 (XLEN 32, no CHERI)\n"), "{isa}");
+        assert!(isa.contains("Target description: rv32i.xml\n"), "{isa}");
+        assert!(
+            isa.ends_with("ELF: 32-bit, e_flags 0x1 (soft float ABI, RVC)\n"),
+            "{isa}"
+        );
+
+        let mut rv64 = machine::<RiscvArch64>("rv64.elf", Vec::new(), Default::default());
+        let mut isa = String::new();
+        rv64.monitor_command("isa", &mut isa);
+        assert!(isa.starts_with("Arch: RV64 (XLEN 64, no CHERI)\n"), "{isa}");
+        assert!(
+            isa.ends_with("ELF: 64-bit, e_flags 0x5 (double float ABI, RVC)\n"),
+            "{isa}"
+        );
+    }
+
+    #[test]
+    fn rawline() {
+        let options = ReadOptions {
+            keep_raw: true,
+            ..Default::default()
         };
+        let trace = ibex_trace::read_trace(&fixture("loop.log"), &options).unwrap();
+        let mut raw = machine::<RiscvArch32>("rv32.elf", trace, Default::default());
+        assert_eq!(
+            monitor(&mut raw, "rawline"),
+            "No event has been executed yet\n"
+        );
+        raw.seek(3);
+        assert_eq!(
+            monitor(&mut raw, "rawline"),
+            "30\t3\t00000004\tc02a\tc.sw\tx10,0(x2)\tPA:0x00000ff0 store:0x00000005\n"
+        );
 
-        Ok(())
+        let mut machine = trace_machine("loop.log", Default::default());
+        machine.seek(3);
+        assert_eq!(
+            monitor(&mut machine, "rawline"),
+            "Raw lines weren't kept; use --keep-raw\n"
+        );
     }
 }
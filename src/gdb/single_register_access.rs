@@ -1,5 +1,10 @@
 use gdbstub::internal::LeBytes;
 use gdbstub::target::{self, TargetResult};
+use num_traits::FromPrimitive as _;
+use riscv_opcodes::{
+    CSR_CYCLE, CSR_CYCLEH, CSR_INSTRET, CSR_INSTRETH, CSR_MCYCLE, CSR_MCYCLEH, CSR_MINSTRET,
+    CSR_MINSTRETH, CSR_TIME, CSR_TIMEH,
+};
 
 use crate::{
     cpu::Privilege,
@@ -7,6 +12,66 @@ use crate::{
     riscv::{RiscvArch, reg::id::RiscvRegId},
 };
 
+impl<A: RiscvArch> Machine<A> {
+    /// Read a counter CSR. Programs rarely write these so they are
+    /// reconstructed from the trace instead: the cycle and time of the
+    /// current event, and the number of instructions retired so far.
+    ///
+    /// On RV32 the `*h` CSRs return the upper 32 bits.
+    fn read_counter_csr(&self, csr: u16) -> Option<A::Usize> {
+        let event = self.current_event().or_else(|| self.trace.first());
+        let cycle = event.map_or(0, |event| event.cycle);
+        let time = event.map_or(0, |event| event.time);
+        let instret = self.trace_index as u64;
+
+        let rv32 = size_of::<A::Usize>() == 4;
+
+        let value = match csr {
+            CSR_CYCLE | CSR_MCYCLE => cycle,
+            CSR_TIME => time,
+            CSR_INSTRET | CSR_MINSTRET => instret,
+            CSR_CYCLEH | CSR_MCYCLEH if rv32 => cycle >> 32,
+            CSR_TIMEH if rv32 => time >> 32,
+            CSR_INSTRETH | CSR_MINSTRETH if rv32 => instret >> 32,
+            _ => return None,
+        };
+
+        A::Usize::from_u64(if rv32 { value & 0xffff_ffff } else { value })
+    }
+
+    /// Read a CSR, or `None` if it hasn't been written in the trace (and
+    /// isn't a counter).
+    pub fn read_csr(&self, csr: u16) -> Option<A::Usize> {
+        self.read_counter_csr(csr)
+            .or_else(|| self.cpu.csrs.get(&csr).copied())
+    }
+
+    /// All the CSRs that can be read, sorted by number.
+    pub fn known_csrs(&self) -> Vec<(u16, A::Usize)> {
+        let counters = [
+            CSR_CYCLE,
+            CSR_TIME,
+            CSR_INSTRET,
+            CSR_MCYCLE,
+            CSR_MINSTRET,
+            CSR_CYCLEH,
+            CSR_TIMEH,
+            CSR_INSTRETH,
+            CSR_MCYCLEH,
+            CSR_MINSTRETH,
+        ];
+        let mut csrs: Vec<u16> = counters
+            .into_iter()
+            .chain(self.cpu.csrs.keys().copied())
+            .collect();
+        csrs.sort_unstable();
+        csrs.dedup();
+        csrs.into_iter()
+            .filter_map(|csr| Some((csr, self.read_csr(csr)?)))
+            .collect()
+    }
+}
+
 impl<A: RiscvArch> target::ext::base::single_register_access::SingleRegisterAccess<()>
     for Machine<A>
 {
@@ -33,7 +98,7 @@ impl<A: RiscvArch> target::ext::base::single_register_access::SingleRegisterAcce
             }
             RiscvRegId::Pc => self.cpu.pc.to_le_bytes(buf).ok_or(().into()),
             RiscvRegId::Csr(reg_id) => {
-                if let Some(reg_val) = self.cpu.csrs.get(&reg_id) {
+                if let Some(reg_val) = self.read_csr(reg_id) {
                     reg_val.to_le_bytes(buf).ok_or(().into())
                 } else {
                     Err(().into())
@@ -49,6 +114,14 @@ impl<A: RiscvArch> target::ext::base::single_register_access::SingleRegisterAcce
                 buf.copy_from_slice(&prv.to_le_bytes());
                 Ok(buf.len())
             }
+            RiscvRegId::Vpr(reg_id) => {
+                // Without `--vlen` there is no vector state.
+                let reg_val = self.cpu.vregs.get(reg_id as usize).ok_or(())?;
+                buf.get_mut(..reg_val.len())
+                    .ok_or(())?
+                    .copy_from_slice(reg_val);
+                Ok(reg_val.len())
+            }
             _ => Err(().into()),
         }
     }
@@ -63,3 +136,100 @@ impl<A: RiscvArch> target::ext::base::single_register_access::SingleRegisterAcce
         Err(().into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use gdbstub::target::ext::base::single_register_access::SingleRegisterAccess as _;
+
+    use super::*;
+    use crate::ibex_trace;
+    use crate::machine::MachineOptions;
+    use crate::riscv::{RiscvArch32, RiscvArch64};
+    use crate::test_util::{fixture, machine, trace_machine};
+
+    fn vector_machine(vlen: Option<usize>) -> Machine<RiscvArch32> {
+        let options = MachineOptions {
+            vlen,
+            ..Default::default()
+        };
+        let mut machine = trace_machine("vector.log", options);
+        machine.seek(2);
+        machine
+    }
+
+    fn read(machine: &mut Machine<RiscvArch32>, reg_id: RiscvRegId<u32>) -> Option<Vec<u8>> {
+        let mut buf = [0; 64];
+        let len = machine.read_register((), reg_id, &mut buf).ok()?;
+        Some(buf[..len].to_vec())
+    }
+
+    #[test]
+    fn read_vector_register() {
+        let mut machine = vector_machine(Some(256));
+        let mut expected: Vec<u8> = (1..=16).rev().collect();
+        expected.resize(32, 0);
+        assert_eq!(read(&mut machine, RiscvRegId::Vpr(3)), Some(expected));
+        // Registers that haven't been written are zero.
+        assert_eq!(read(&mut machine, RiscvRegId::Vpr(4)), Some(vec![0; 32]));
+    }
+
+    #[test]
+    fn vector_registers_need_vlen() {
+        let mut machine = vector_machine(None);
+        assert_eq!(read(&mut machine, RiscvRegId::Vpr(3)), None);
+    }
+
+    #[test]
+    fn counter_csrs() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        machine.seek(6);
+        let event = &machine.trace[5];
+        let (cycle, time) = (event.cycle as u32, event.time as u32);
+        for (csr, expected) in [
+            (CSR_CYCLE, cycle),
+            (CSR_MCYCLE, cycle),
+            (CSR_TIME, time),
+            (CSR_INSTRET, 6),
+            (CSR_MINSTRET, 6),
+            (CSR_CYCLEH, 0),
+            (CSR_TIMEH, 0),
+            (CSR_INSTRETH, 0),
+        ] {
+            assert_eq!(machine.read_csr(csr), Some(expected), "{csr:#x}");
+        }
+
+        // The high halves on RV32.
+        machine.trace[5].cycle = 0x3_0000_0007;
+        machine.trace[5].time = 0x4_0000_0009;
+        for (csr, expected) in [
+            (CSR_CYCLE, 7),
+            (CSR_CYCLEH, 3),
+            (CSR_MCYCLEH, 3),
+            (CSR_TIME, 9),
+            (CSR_TIMEH, 4),
+        ] {
+            assert_eq!(machine.read_csr(csr), Some(expected), "{csr:#x}");
+        }
+    }
+
+    #[test]
+    fn counter_csrs_rv64() {
+        let mut trace =
+            ibex_trace::read_trace::<u64>(&fixture("loop.log"), &Default::default()).unwrap();
+        trace[5].cycle = 0x3_0000_0007;
+        let mut machine = machine::<RiscvArch64>("rv64.elf", trace, Default::default());
+        machine.seek(6);
+        assert_eq!(machine.read_csr(CSR_CYCLE), Some(0x3_0000_0007));
+        assert_eq!(machine.read_csr(CSR_INSTRET), Some(6));
+        // There are no high halves on RV64.
+        for csr in [
+            CSR_CYCLEH,
+            CSR_MCYCLEH,
+            CSR_TIMEH,
+            CSR_INSTRETH,
+            CSR_MINSTRETH,
+        ] {
+            assert_eq!(machine.read_csr(csr), None, "{csr:#x}");
+        }
+    }
+}
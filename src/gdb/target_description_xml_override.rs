@@ -0,0 +1,93 @@
+use crate::machine::Machine;
+use crate::riscv::RiscvArch;
+
+use super::copy_range_to_buf;
+use gdbstub::arch::Arch;
+use gdbstub::target;
+use gdbstub::target::TargetError;
+use gdbstub::target::TargetResult;
+use num_traits::ToPrimitive;
+
+// gdbstub doesn't support custom qXfer objects, so we piggyback on the
+// features object, which allows any annex.
+const DISAS_ANNEX_PREFIX: &[u8] = b"rtd-disas/";
+
+impl<A: RiscvArch> target::ext::target_description_xml_override::TargetDescriptionXmlOverride
+    for Machine<A>
+{
+    /// As well as the normal `target.xml`, this serves the retired assembly
+    /// for each trace event so that a cooperating front-end can display it:
+    ///
+    /// ```text
+    /// qXfer:features:read:rtd-disas/<index>:<offset>,<length>
+    /// ```
+    ///
+    /// where `<index>` is the decimal trace index. The object is a single
+    /// line of the form `<index>\t<pc>\t<mnemonic>\t<args>\n`, where `<pc>`
+    /// is hex with a `0x` prefix and the mnemonic and arguments are exactly
+    /// as they appear in the trace.
+    fn target_description_xml(
+        &self,
+        annex: &[u8],
+        offset: u64,
+        length: usize,
+        buf: &mut [u8],
+    ) -> TargetResult<usize, Self> {
+        if annex == b"target.xml" {
+            let xml = <A::BaseArch as Arch>::target_description_xml().unwrap_or_default();
+            return Ok(copy_range_to_buf(xml.as_bytes(), offset, length, buf));
+        }
+
+        let index: usize = annex
+            .strip_prefix(DISAS_ANNEX_PREFIX)
+            .and_then(|index| std::str::from_utf8(index).ok())
+            .and_then(|index| index.parse().ok())
+            .ok_or(TargetError::NonFatal)?;
+
+        let event = self.trace.get(index).ok_or(TargetError::NonFatal)?;
+
+        let line = format!(
+            "{index}\t{:#x}\t{}\t{}\n",
+            event.pc.to_u64().unwrap_or_default(),
+            event.assembly_mnemonic,
+            event.assembly_args,
+        );
+
+        Ok(copy_range_to_buf(line.as_bytes(), offset, length, buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gdbstub::target::ext::target_description_xml_override::TargetDescriptionXmlOverride as _;
+
+    use crate::test_util::trace_machine;
+
+    fn read(annex: &str, offset: u64, length: usize) -> Option<String> {
+        let machine = trace_machine("loop.log", Default::default());
+        let mut buf = [0; 64];
+        let len = machine
+            .target_description_xml(annex.as_bytes(), offset, length, &mut buf)
+            .ok()?;
+        Some(String::from_utf8(buf[..len].to_vec()).unwrap())
+    }
+
+    #[test]
+    fn rtd_disas() {
+        let line = "2\t0x4\tc.sw\tx10,0(x2)\n";
+        assert_eq!(read("rtd-disas/2", 0, 1000).as_deref(), Some(line));
+
+        // Read in chunks like GDB does.
+        assert_eq!(read("rtd-disas/2", 0, 5).as_deref(), Some(&line[..5]));
+        assert_eq!(read("rtd-disas/2", 5, 5).as_deref(), Some(&line[5..10]));
+        assert_eq!(read("rtd-disas/2", 10, 100).as_deref(), Some(&line[10..]));
+        assert_eq!(
+            read("rtd-disas/2", line.len() as u64, 100).as_deref(),
+            Some("")
+        );
+
+        assert_eq!(read("rtd-disas/100", 0, 100), None);
+        assert_eq!(read("rtd-disas/x", 0, 100), None);
+        assert_eq!(read("other.xml", 0, 100), None);
+    }
+}
@@ -1,45 +1,55 @@
-use std::{
-    fs::File,
-    io::{BufRead as _, BufReader},
-    path::Path,
-};
+use std::path::Path;
 
 use anyhow::{Context, Result, anyhow, bail};
 use num_traits::Num;
 
-use crate::trace::{Data, MemWrite, TraceEvent, XRegWrite};
+use crate::decode;
+use crate::trace::{
+    Data, MemWrite, ReadOptions, TraceColumns, TraceEvent, UnknownTokens, VRegWrite, XRegWrite,
+    has_trap_marker, is_failed_store_conditional, read_trace_file, xreg_write_token,
+};
 
-fn read_line<Usize: Num>(line: &str) -> Result<TraceEvent<Usize>> {
+fn read_line<Usize: Num>(
+    line: &str,
+    columns: &TraceColumns,
+    unknown: &mut UnknownTokens,
+) -> Result<TraceEvent<Usize>> {
     let parts: Vec<&str> = line.split('\t').collect();
 
-    if parts.len() < 4 {
+    if parts.len() < columns.required_len() {
         bail!(
-            "expected at least 4 tab-separated values, got {}",
+            "expected at least {} tab-separated values, got {}",
+            columns.required_len(),
             parts.len()
         );
     }
 
-    let time_str = parts[0].trim();
-    let cycle_str = parts[1].trim();
-    let pc_str = parts[2];
-    let instruction_str = parts[3].trim();
+    let time_str = parts[columns.time].trim();
+    let cycle_str = parts[columns.cycle].trim();
+    let pc_str = parts[columns.pc];
+    let instruction_str = parts[columns.insn].trim();
 
-    let time =
-        u64::from_str_radix(time_str, 10).with_context(|| format!("parsing {time_str:?}"))?;
-    let cycle =
-        u64::from_str_radix(cycle_str, 10).with_context(|| format!("parsing {cycle_str:?}"))?;
+    let time: u64 = time_str
+        .parse()
+        .with_context(|| format!("parsing {time_str:?}"))?;
+    let cycle: u64 = cycle_str
+        .parse()
+        .with_context(|| format!("parsing {cycle_str:?}"))?;
     let pc = Usize::from_str_radix(pc_str, 16).map_err(|_| anyhow!("parsing {pc_str:?}"))?;
     let instruction = u32::from_str_radix(instruction_str, 16)
         .with_context(|| format!("parsing {instruction_str:?}"))?;
 
-    let assembly_mnemonic = parts.get(4).map(|s| s.to_owned());
-    let assembly_args = parts.get(5).map(|s| s.to_owned());
+    let column = |index: Option<usize>| index.and_then(|i| parts.get(i)).map(|s| s.to_owned());
 
-    let accesses = parts.get(6).map(|s| s.to_owned());
+    let assembly_mnemonic = column(columns.mnemonic);
+    let assembly_args = column(columns.args);
+
+    let accesses = column(columns.accesses);
 
     let mut phys_addr = None;
     let mut store_val = None;
     let mut xwrite = None;
+    let mut vwrite = None;
 
     if let Some(accesses) = accesses {
         let access_parts = accesses.split_ascii_whitespace();
@@ -57,25 +67,38 @@ fn read_line<Usize: Num>(line: &str) -> Result<TraceEvent<Usize>> {
                 }
                 phys_addr =
                     Some(u64::from_str_radix(val, 16).with_context(|| format!("parsing {val:?}"))?);
-            } else {
-                for index in 1..32 {
-                    if let Some(val) = part.strip_prefix(&format!("x{index}=0x")) {
-                        if xwrite.is_some() {
-                            bail!("Multiple X writes found");
-                        }
-                        let value = Usize::from_str_radix(val, 16)
-                            .map_err(|_| anyhow!("parsing {val:?}"))?;
-                        xwrite = Some(XRegWrite {
-                            index,
-                            value,
-                            prev_value: None,
-                        });
-                    }
+            } else if part.starts_with("load:") || part.starts_with("x0=") {
+                // Loads don't change the state and writes to x0 are discarded.
+            } else if let Some(write) = VRegWrite::parse(part) {
+                if vwrite.is_some() {
+                    bail!("Multiple V writes found");
                 }
+                vwrite = Some(write?);
+            } else if let Some((index, val)) = xreg_write_token(part)
+                && let Some(val) = val.strip_prefix("0x")
+            {
+                if xwrite.is_some() {
+                    bail!("Multiple X writes found");
+                }
+                let value =
+                    Usize::from_str_radix(val, 16).map_err(|_| anyhow!("parsing {val:?}"))?;
+                xwrite = Some(XRegWrite {
+                    index,
+                    value,
+                    metadata: None,
+                    prev_value: None,
+                    prev_metadata: None,
+                });
+            } else {
+                unknown.add(part);
             }
         }
     }
 
+    if is_failed_store_conditional(instruction, xwrite.as_ref()) {
+        store_val = None;
+    }
+
     let store = match (store_val, phys_addr) {
         (Some(val), Some(phys_addr)) => {
             // Ibex uses the same number format for all stores so the only
@@ -100,7 +123,9 @@ fn read_line<Usize: Num>(line: &str) -> Result<TraceEvent<Usize>> {
             Some(MemWrite {
                 phys_addr,
                 value,
+                tag: None,
                 prev_value: None,
+                prev_tags: Vec::new(),
             })
         }
         (None, _) => None,
@@ -111,44 +136,26 @@ fn read_line<Usize: Num>(line: &str) -> Result<TraceEvent<Usize>> {
         time,
         cycle,
         pc,
-        trap: false,
+        trap: assembly_mnemonic.is_some_and(has_trap_marker),
         instruction: Some(instruction),
         assembly_mnemonic: assembly_mnemonic.unwrap_or_default().to_owned(),
         assembly_args: assembly_args.unwrap_or_default().to_owned(),
         xwrite,
+        vwrite,
         store,
+        csr_writes: Vec::new(),
+        raw: None,
+        line: 0,
     })
 }
 
-pub fn read_trace<Usize: Num>(file_path: &Path) -> Result<Vec<TraceEvent<Usize>>> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-
-    let mut events = Vec::new();
-
-    for (line_number, line) in reader.lines().enumerate() {
-        let line_number_plus_one = line_number + 1;
-        let line = line.with_context(|| {
-            format!(
-                "reading line {}:{line_number_plus_one}",
-                file_path.display()
-            )
-        })?;
-
-        if line.starts_with("Time") {
-            // Skip header.
-            continue;
-        }
-
-        events.push(read_line(&line).with_context(|| {
-            format!(
-                "processing line {}:{line_number_plus_one}",
-                file_path.display()
-            )
-        })?);
-    }
-
-    Ok(events)
+pub fn read_trace<Usize: Num>(
+    file_path: &Path,
+    options: &ReadOptions,
+) -> Result<Vec<TraceEvent<Usize>>> {
+    read_trace_file(file_path, options, |line, unknown| {
+        read_line(line, &options.columns, unknown)
+    })
 }
 
 enum AccessWidth {
@@ -162,27 +169,180 @@ fn instruction_access_width(instruction: u32) -> Option<AccessWidth> {
     // instructions that Ibex supports. The list of
     // supported Ibex instructions is in its `rtl/ibex_tracer_pkg.sv` file.
 
-    if instruction & riscv_opcodes::MASK_LB == riscv_opcodes::MATCH_LB {
-        Some(AccessWidth::Byte)
-    } else if instruction & riscv_opcodes::MASK_LH == riscv_opcodes::MATCH_LH {
-        Some(AccessWidth::Half)
-    } else if instruction & riscv_opcodes::MASK_LW == riscv_opcodes::MATCH_LW {
-        Some(AccessWidth::Word)
-    } else if instruction & riscv_opcodes::MASK_SB == riscv_opcodes::MATCH_SB {
+    let is = decode::matcher(instruction);
+
+    if is(riscv_opcodes::MASK_LB, riscv_opcodes::MATCH_LB)
+        || is(riscv_opcodes::MASK_SB, riscv_opcodes::MATCH_SB)
+    {
         Some(AccessWidth::Byte)
-    } else if instruction & riscv_opcodes::MASK_SH == riscv_opcodes::MATCH_SH {
+    } else if is(riscv_opcodes::MASK_LH, riscv_opcodes::MATCH_LH)
+        || is(riscv_opcodes::MASK_SH, riscv_opcodes::MATCH_SH)
+    {
         Some(AccessWidth::Half)
-    } else if instruction & riscv_opcodes::MASK_SW == riscv_opcodes::MATCH_SW {
-        Some(AccessWidth::Word)
-    } else if instruction & riscv_opcodes::MASK_C_LW == riscv_opcodes::MATCH_C_LW {
-        Some(AccessWidth::Word)
-    } else if instruction & riscv_opcodes::MASK_C_SW == riscv_opcodes::MATCH_C_SW {
-        Some(AccessWidth::Word)
-    } else if instruction & riscv_opcodes::MASK_C_LWSP == riscv_opcodes::MATCH_C_LWSP {
-        Some(AccessWidth::Word)
-    } else if instruction & riscv_opcodes::MASK_C_SWSP == riscv_opcodes::MATCH_C_SWSP {
+    } else if is(riscv_opcodes::MASK_LW, riscv_opcodes::MATCH_LW)
+        || is(riscv_opcodes::MASK_SW, riscv_opcodes::MATCH_SW)
+        || is(riscv_opcodes::MASK_C_LW, riscv_opcodes::MATCH_C_LW)
+        || is(riscv_opcodes::MASK_C_SW, riscv_opcodes::MATCH_C_SW)
+        || is(riscv_opcodes::MASK_C_LWSP, riscv_opcodes::MATCH_C_LWSP)
+        || is(riscv_opcodes::MASK_C_SWSP, riscv_opcodes::MATCH_C_SWSP)
+        || is_atomic_word(instruction)
+    {
         Some(AccessWidth::Word)
     } else {
         None
     }
 }
+
+/// Whether the instruction is a 32-bit atomic (`lr.w`, `sc.w` or an AMO).
+/// AMOs read and write memory in one instruction but the trace's store is
+/// just the result, so the previous value still comes from memory.
+fn is_atomic_word(instruction: u32) -> bool {
+    let is = decode::matcher(instruction);
+
+    is(riscv_opcodes::MASK_LR_W, riscv_opcodes::MATCH_LR_W)
+        || is(riscv_opcodes::MASK_SC_W, riscv_opcodes::MATCH_SC_W)
+        || is(
+            riscv_opcodes::MASK_AMOSWAP_W,
+            riscv_opcodes::MATCH_AMOSWAP_W,
+        )
+        || is(riscv_opcodes::MASK_AMOADD_W, riscv_opcodes::MATCH_AMOADD_W)
+        || is(riscv_opcodes::MASK_AMOXOR_W, riscv_opcodes::MATCH_AMOXOR_W)
+        || is(riscv_opcodes::MASK_AMOAND_W, riscv_opcodes::MATCH_AMOAND_W)
+        || is(riscv_opcodes::MASK_AMOOR_W, riscv_opcodes::MATCH_AMOOR_W)
+        || is(riscv_opcodes::MASK_AMOMIN_W, riscv_opcodes::MATCH_AMOMIN_W)
+        || is(riscv_opcodes::MASK_AMOMAX_W, riscv_opcodes::MATCH_AMOMAX_W)
+        || is(
+            riscv_opcodes::MASK_AMOMINU_W,
+            riscv_opcodes::MATCH_AMOMINU_W,
+        )
+        || is(
+            riscv_opcodes::MASK_AMOMAXU_W,
+            riscv_opcodes::MATCH_AMOMAXU_W,
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::fixture;
+
+    /// The fields of an event that every layout has.
+    fn summary(event: &TraceEvent<u32>) -> String {
+        format!(
+            "{} {} {:#x} {:x?} {:x?} {:x?}",
+            event.time,
+            event.cycle,
+            event.pc,
+            event.instruction,
+            event.xwrite.as_ref().map(|w| (w.index, w.value)),
+            event
+                .store
+                .as_ref()
+                .map(|s| (s.phys_addr, s.value.to_u128())),
+        )
+    }
+
+    fn read_fixture(name: &str, options: &ReadOptions) -> Vec<TraceEvent<u32>> {
+        read_trace(&fixture(name), options).unwrap()
+    }
+
+    #[test]
+    fn reordered_columns() {
+        let options = ReadOptions {
+            columns: "pc=0,time=1,cycle=2,insn=3,accesses=4".parse().unwrap(),
+            ..Default::default()
+        };
+        let reordered = read_fixture("reordered.log", &options);
+        let ibex = read_fixture("loop.log", &Default::default());
+        assert_eq!(
+            reordered.iter().map(summary).collect::<Vec<_>>(),
+            ibex.iter().map(summary).collect::<Vec<_>>()
+        );
+        // There is no mnemonic column.
+        assert_eq!(reordered[0].assembly_mnemonic, "");
+    }
+
+    #[test]
+    fn unknown_tokens_are_ignored() {
+        let line = "10\t1\t00000000\t1141\tc.addi\tx2,x2,-16\tfoo:0x1 x2=0x00000ff0 csr:mstatus=1";
+        let mut unknown = UnknownTokens::default();
+        let event: TraceEvent<u32> = read_line(line, &Default::default(), &mut unknown).unwrap();
+        assert_eq!(summary(&event), "10 1 0x0 Some(1141) Some((2, ff0)) None");
+        assert_eq!(
+            unknown.summary().as_deref(),
+            Some(r#""csr:" x1, "foo:" x1"#)
+        );
+
+        // The rest of the trace is the same as without them.
+        let with_unknown = read_fixture("unknown_tokens.log", &Default::default());
+        let ibex = read_fixture("loop.log", &Default::default());
+        assert_eq!(
+            with_unknown.iter().map(summary).collect::<Vec<_>>(),
+            ibex.iter().map(summary).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn trap_marker() {
+        let trace = read_fixture("ibex_trap.log", &Default::default());
+        assert_eq!(
+            trace.iter().map(|event| event.trap).collect::<Vec<_>>(),
+            [false, true, false]
+        );
+        assert_eq!(trace[1].assembly_mnemonic, "--> illegal");
+        assert_eq!(trace[1].mnemonic(), "illegal");
+        assert_eq!(trace[0].mnemonic(), "c.li");
+    }
+
+    #[test]
+    fn decode_missing_mnemonics() {
+        let options = ReadOptions {
+            columns: "pc=0,time=1,cycle=2,insn=3,accesses=4".parse().unwrap(),
+            decode: true,
+            ..Default::default()
+        };
+        let trace = read_fixture("reordered.log", &options);
+        assert_eq!(trace[0].assembly_mnemonic, "c.addi");
+        assert_eq!(trace[0].assembly_args, decode::DECODED_ARGS);
+        assert_eq!(trace[13].assembly_mnemonic, "addi");
+
+        // Mnemonics from the trace are kept.
+        let options = ReadOptions {
+            decode: true,
+            ..Default::default()
+        };
+        let trace = read_fixture("loop.log", &options);
+        assert_eq!(trace[0].assembly_args, "x2,x2,-16");
+    }
+
+    #[test]
+    fn line_numbers() {
+        // Line 1 is the header.
+        let trace = read_fixture("loop.log", &Default::default());
+        assert_eq!(
+            trace.iter().map(|event| event.line).collect::<Vec<_>>(),
+            (2..=16).collect::<Vec<_>>()
+        );
+
+        let source = std::fs::read_to_string(fixture("loop.log")).unwrap();
+        let line = source.lines().nth(trace[4].line as usize - 1).unwrap();
+        assert!(line.starts_with("50\t5\t00000008\t"), "{line}");
+    }
+
+    #[test]
+    fn keep_raw_lines() {
+        let source = std::fs::read_to_string(fixture("loop.log")).unwrap();
+        let options = ReadOptions {
+            keep_raw: true,
+            ..Default::default()
+        };
+        let trace = read_fixture("loop.log", &options);
+        for event in &trace {
+            let line = source.lines().nth(event.line as usize - 1);
+            assert_eq!(event.raw.as_deref(), line);
+        }
+
+        let trace = read_fixture("loop.log", &Default::default());
+        assert!(trace.iter().all(|event| event.raw.is_none()));
+    }
+}
@@ -0,0 +1,106 @@
+//! Exporting the reconstructed memory as a sparse image for other tools.
+//!
+//! The file format is little endian binary:
+//!
+//! * Magic `RTDIMAGE`
+//! * Version (u32)
+//! * Segment count (u32) followed by (address: u64, length: u64) pairs,
+//!   sorted by address
+//! * The contents of each segment, in order
+//!
+//! Memory that has never been written (by the ELF or the trace) isn't
+//! included, so there is a segment for each contiguous run of known bytes.
+
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+
+use crate::machine::Machine;
+use crate::riscv::RiscvArch;
+
+const MAGIC: &[u8; 8] = b"RTDIMAGE";
+const VERSION: u32 = 1;
+
+impl<A: RiscvArch> Machine<A> {
+    /// Write the current memory contents (as the debugger sees them, i.e.
+    /// including patches) to `path`, optionally limited to `range`. Returns
+    /// the number of segments and bytes written.
+    pub fn export_image(&self, path: &Path, range: Option<Range<u64>>) -> Result<(usize, u64)> {
+        let segments = self.mem.segments(range);
+
+        let file =
+            std::fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        let mut w = BufWriter::new(file);
+
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&(segments.len() as u32).to_le_bytes())?;
+        for (addr, data) in &segments {
+            w.write_all(&addr.to_le_bytes())?;
+            w.write_all(&(data.len() as u64).to_le_bytes())?;
+        }
+        for (_, data) in &segments {
+            w.write_all(data)?;
+        }
+        w.flush()?;
+
+        let bytes = segments.iter().map(|(_, data)| data.len() as u64).sum();
+        Ok((segments.len(), bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::trace_machine;
+
+    /// Parse an image back into its segments.
+    fn read_image(path: &Path) -> Vec<(u64, Vec<u8>)> {
+        let bytes = std::fs::read(path).unwrap();
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+
+        assert_eq!(&bytes[..8], MAGIC);
+        assert_eq!(u32_at(8), VERSION);
+        let count = u32_at(12) as usize;
+        let mut data = 16 + count * 16;
+        let mut segments = Vec::new();
+        for i in 0..count {
+            let addr = u64_at(16 + i * 16);
+            let len = u64_at(24 + i * 16) as usize;
+            segments.push((addr, bytes[data..data + len].to_vec()));
+            data += len;
+        }
+        assert_eq!(data, bytes.len());
+        segments
+    }
+
+    #[test]
+    fn export_after_stores() {
+        let path = std::env::temp_dir().join(format!("rtd-image-{}", std::process::id()));
+        let mut machine = trace_machine("amo.log", Default::default());
+        machine.seek(machine.trace.len());
+        machine.mem.patch(0xff4, 0xaa);
+
+        assert_eq!(
+            machine.export_image(&path, Some(0xf00..0x1000)).unwrap(),
+            (1, 5)
+        );
+        assert_eq!(read_image(&path), [(0xff0, vec![9, 0, 0, 0, 0xaa])]);
+
+        // The whole image also has the ELF's sections, in address order.
+        let (segments, bytes) = machine.export_image(&path, None).unwrap();
+        let image = read_image(&path);
+        assert_eq!(image.len(), segments);
+        assert_eq!(
+            image.iter().map(|(_, data)| data.len() as u64).sum::<u64>(),
+            bytes
+        );
+        assert!(image.is_sorted_by_key(|(addr, _)| *addr));
+        assert!(image.contains(&(0xff0, vec![9, 0, 0, 0, 0xaa])));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
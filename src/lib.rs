@@ -0,0 +1,25 @@
+//! Reconstruction of RISC-V register and memory state from instruction
+//! traces, and a gdbserver for debugging them.
+
+pub mod buffered_connection;
+pub mod capability;
+pub mod check;
+pub mod cheriot_ibex_trace;
+pub mod cpu;
+pub mod decode;
+pub mod expr;
+mod gdb;
+pub mod ibex_trace;
+mod image;
+pub mod logging;
+pub mod machine;
+mod mem_sniffer;
+pub mod memory;
+mod parse;
+mod reg_dump;
+pub mod riscv;
+mod snapshot;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod trace;
+pub mod trap;
@@ -1,25 +1,38 @@
 use crate::cpu::Cpu;
-use crate::mem_sniffer::AccessKind;
+use crate::decode;
+use crate::expr::Condition;
 use crate::mem_sniffer::MemSniffer;
 use crate::memory::Memory;
 use crate::memory::SimpleMemory;
+use crate::reg_dump::apply_init_regs;
 use crate::riscv::RiscvArch;
-use crate::trace::TraceEvent;
+use crate::riscv::reg::names::GPR_ABI_NAMES;
+use crate::trace::{TraceEvent, infer_trap_csrs};
+use crate::trap::TrapSignals;
 
 use anyhow::Context;
 use anyhow::Result;
 use anyhow::anyhow;
+use anyhow::bail;
 use gdbstub::common::Signal;
 use gdbstub::stub::SingleThreadStopReason;
+use gdbstub::target::ext::base::reverse_exec::ReplayLogPosition;
 use gdbstub::target::ext::tracepoints::NewTracepoint;
 use gdbstub::target::ext::tracepoints::SourceTracepoint;
 use gdbstub::target::ext::tracepoints::Tracepoint;
 use gdbstub::target::ext::tracepoints::TracepointAction;
 use gdbstub::target::ext::tracepoints::TracepointEnumerateState;
-use log::info;
+use goblin::elf::section_header::SHF_TLS;
+use goblin::elf::section_header::SHT_NOBITS;
+use log::{info, warn};
 use num_traits::FromPrimitive as _;
-use num_traits::ToPrimitive;
+use num_traits::ToPrimitive as _;
+use riscv_opcodes::CSR_MCAUSE;
+use riscv_opcodes::CSR_MTVAL;
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use tokio::sync::watch::Sender;
 use tokio::task::yield_now;
 
@@ -35,18 +48,116 @@ pub enum ExecDir {
     Backwards,
 }
 
+/// A tracepoint definition along with its source strings and actions.
+pub type TracepointDefinition<A> = (
+    NewTracepoint<<A as RiscvArch>::Usize>,
+    Vec<SourceTracepoint<'static, <A as RiscvArch>::Usize>>,
+    Vec<TracepointAction<'static, <A as RiscvArch>::Usize>>,
+);
+
 #[derive(Debug)]
 pub struct TraceFrame<A: RiscvArch> {
     pub number: Tracepoint,
     pub snapshot: Cpu<A::Usize>,
 }
 
+/// Settings for the `Machine` that come from the command line.
+#[derive(Default, Clone)]
+pub struct MachineOptions {
+    /// Allow the debugger to write memory. Writes go to an overlay so they
+    /// don't affect the replay.
+    pub allow_writes: bool,
+    /// Directory that host I/O file accesses are confined to. This must be
+    /// canonical.
+    pub host_io_root: Option<PathBuf>,
+    /// Stop when a trap is executed, reporting a signal based on its cause.
+    pub stop_on_trap: bool,
+    pub trap_signals: TrapSignals,
+    /// Vector register length in bits. Vector registers are only available
+    /// if this is set.
+    pub vlen: Option<usize>,
+    /// Absolute path the ELF is served at over host I/O, instead of
+    /// `/test.elf`.
+    pub exec_name: Option<String>,
+    /// Fail instead of warning when the ELF looks wrong, e.g. sections that
+    /// overlap.
+    pub strict: bool,
+    /// Record memory stores as they are replayed (`monitor access-log`),
+    /// keeping at most this many.
+    pub record_accesses: Option<usize>,
+    /// Register dump (see `reg_dump`) of the registers at the start of the
+    /// trace, for traces that don't start from reset.
+    pub init_regs: Option<PathBuf>,
+    /// Name of the trace format that was read, for `monitor isa`.
+    pub trace_format: &'static str,
+}
+
+/// The replay position, published on a watch channel for anything that
+/// follows along (Surfer, the TUI).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayStatus {
+    pub time: u64,
+    pub cycle: u64,
+    pub pc: u64,
+    pub trace_index: usize,
+    pub trace_len: usize,
+    pub reverse: bool,
+    /// Set while continuing, when the position changes very frequently.
+    pub running: bool,
+}
+
+/// Capability tags covered by a memory read.
+#[derive(Debug, Default, Clone)]
+pub struct ReadTags {
+    pub addr: u64,
+    pub len: u64,
+    /// Tagged slots overlapping the read, with whether the read covered
+    /// the whole slot. Part of a capability is never a valid capability.
+    pub slots: Vec<(u64, bool)>,
+}
+
+/// A memory store recorded by `--record-accesses`.
+#[derive(Debug, Clone)]
+pub struct AccessRecord {
+    pub index: usize,
+    pub pc: u64,
+    pub cycle: u64,
+    pub addr: u64,
+    pub size: usize,
+    pub value: u128,
+}
+
+/// Forward iteration over reconstructed states. See `Machine::iter_states()`.
+pub struct States<'a, A: RiscvArch> {
+    machine: &'a mut Machine<A>,
+}
+
+impl<A: RiscvArch> States<'_, A> {
+    /// Apply the next trace event and return its index along with the
+    /// resulting CPU and memory state, or `None` at the end of the trace.
+    ///
+    /// This isn't an `Iterator` because the CPU and memory are updated in
+    /// place, so the returned references borrow from `self` and must be
+    /// dropped before calling this again. Clone them if you need to keep
+    /// them.
+    pub fn next_state(&mut self) -> Option<(usize, &Cpu<A::Usize>, &SimpleMemory)> {
+        let index = self.machine.trace_index;
+        if index >= self.machine.trace.len() {
+            return None;
+        }
+        self.machine.seek(index + 1);
+        Some((index, &self.machine.cpu, &self.machine.mem))
+    }
+}
+
 /// "Emulator" for RISC-V trace file. It reconstructs registers and
 /// memory contents.
 pub struct Machine<A: RiscvArch> {
     pub exec_mode: ExecMode<A>,
     pub exec_dir: ExecDir,
 
+    pub options: MachineOptions,
+
     pub cpu: Cpu<A::Usize>,
     pub mem: SimpleMemory,
 
@@ -59,32 +170,47 @@ pub struct Machine<A: RiscvArch> {
 
     // Entry point (needed so we can put it in AuxV).
     pub entry: A::Usize,
+    // PC before the first event (`--init-regs` can override it).
+    pub initial_pc: A::Usize,
 
     pub watchpoints: Vec<A::Usize>,
-    pub breakpoints: Vec<A::Usize>,
+    // GPRs to stop on writes to (`monitor watch-reg`).
+    pub watched_regs: BTreeSet<u8>,
+    pub breakpoints: BTreeSet<A::Usize>,
+    // Conditions for breakpoints (`monitor bcond`). The breakpoint is
+    // ignored when its condition is false.
+    pub breakpoint_conditions: BTreeMap<A::Usize, Condition>,
+    // Number of times to pass breakpoints without stopping (`monitor
+    // ignore`). Counts are never zero; the entry is removed instead.
+    pub breakpoint_ignore_counts: BTreeMap<A::Usize, u64>,
     pub files: Vec<Option<std::fs::File>>,
 
-    pub tracepoints: BTreeMap<
-        Tracepoint,
-        (
-            NewTracepoint<A::Usize>,
-            Vec<SourceTracepoint<'static, A::Usize>>,
-            Vec<TracepointAction<'static, A::Usize>>,
-        ),
-    >,
+    pub tracepoints: BTreeMap<Tracepoint, TracepointDefinition<A>>,
     pub traceframes: Vec<TraceFrame<A>>,
     pub tracepoint_enumerate_state: TracepointEnumerateState<A::Usize>,
     pub tracing: bool,
     pub selected_frame: Option<usize>,
 
-    send_time: Sender<u64>,
+    // Tags for the last memory read by the debugger (`monitor tags`).
+    pub last_read_tags: Option<ReadTags>,
+
+    // Stores seen so far, oldest first, if `record_accesses` is set.
+    pub access_log: VecDeque<AccessRecord>,
+    // Whether old records have been dropped to stay within the limit.
+    pub access_log_truncated: bool,
+
+    // Text for the GDB console, sent before the next stop reply.
+    pub console_output: String,
+
+    send_status: Sender<ReplayStatus>,
 }
 
 impl<A: RiscvArch> Machine<A> {
     pub fn new(
         elf: Vec<u8>,
-        trace: Vec<TraceEvent<A::Usize>>,
-        send_time: Sender<u64>,
+        mut trace: Vec<TraceEvent<A::Usize>>,
+        options: MachineOptions,
+        send_status: Sender<ReplayStatus>,
     ) -> Result<Machine<A>> {
         // set up emulated system
         let mut cpu = Cpu::<A::Usize>::default();
@@ -93,10 +219,7 @@ impl<A: RiscvArch> Machine<A> {
         let elf_header = goblin::elf::Elf::parse(&elf)?;
 
         // copy all in-memory sections from the ELF file into system RAM
-        let sections = elf_header
-            .section_headers
-            .iter()
-            .filter(|h| h.is_alloc() && h.sh_type != goblin::elf::section_header::SHT_NOBITS);
+        let sections = elf_header.section_headers.iter().filter(|h| h.is_alloc());
 
         let entry = A::Usize::from_u64(elf_header.entry).ok_or_else(|| {
             anyhow!(
@@ -107,16 +230,64 @@ impl<A: RiscvArch> Machine<A> {
 
         // TODO: Initialise tags.
 
+        // Sections loaded so far, to detect overlaps.
+        let mut loaded: Vec<(&str, std::ops::Range<u64>)> = Vec::new();
+
         for h in sections {
-            info!(
-                "loading section {:?} into memory from [{:#010x?}..{:#010x?}]",
-                elf_header
-                    .shdr_strtab
-                    .get_at(h.sh_name)
-                    .context("section name string access")?,
-                h.sh_addr,
-                h.sh_addr + h.sh_size,
-            );
+            let name = elf_header
+                .shdr_strtab
+                .get_at(h.sh_name)
+                .context("section name string access")?;
+            let is_tls = h.sh_flags & SHF_TLS as u64 != 0;
+
+            if h.sh_type == SHT_NOBITS {
+                // `.tbss` takes no space in the image; its address range
+                // overlaps whatever follows `.tdata`, so loading it (even as
+                // zeros) would clobber real data.
+                if is_tls {
+                    info!(
+                        "skipping TLS section {name:?} at [{:#010x?}..{:#010x?}]",
+                        h.sh_addr,
+                        h.sh_addr + h.sh_size,
+                    );
+                }
+                continue;
+            }
+
+            if is_tls {
+                // This is only the initialisation image. The runtime copies it
+                // into per-thread (or per-compartment) storage, so the trace
+                // will access thread locals at different addresses.
+                info!(
+                    "loading TLS initialisation image {name:?} into memory from [{:#010x?}..{:#010x?}]",
+                    h.sh_addr,
+                    h.sh_addr + h.sh_size,
+                );
+            } else {
+                info!(
+                    "loading section {name:?} into memory from [{:#010x?}..{:#010x?}]",
+                    h.sh_addr,
+                    h.sh_addr + h.sh_size,
+                );
+            }
+
+            // Empty sections (e.g. an unused `.init_array`) can't overlap
+            // anything even if their address is inside another section.
+            let range = h.sh_addr..h.sh_addr + h.sh_size;
+            if let Some((other, other_range)) = loaded
+                .iter()
+                .find(|(_, r)| !range.is_empty() && r.start < range.end && range.start < r.end)
+            {
+                let message = format!(
+                    "section {name:?} [{:#010x?}..{:#010x?}] overlaps section {other:?} [{:#010x?}..{:#010x?}]",
+                    range.start, range.end, other_range.start, other_range.end,
+                );
+                if options.strict {
+                    bail!(message);
+                }
+                warn!("{message}");
+            }
+            loaded.push((name, range));
 
             for (i, b) in elf[h
                 .file_range()
@@ -135,22 +306,54 @@ impl<A: RiscvArch> Machine<A> {
         // trace entries though.
         cpu.pc = trace.first().map(|t| t.pc).unwrap_or(entry);
 
-        Ok(Machine {
+        if let Some(path) = &options.init_regs {
+            apply_init_regs(&mut cpu, path)?;
+        }
+        let initial_pc = cpu.pc;
+
+        infer_trap_csrs(&mut trace);
+
+        let vwrites = trace
+            .iter()
+            .enumerate()
+            .filter_map(|(i, t)| Some((i, t.vwrite.as_ref()?)));
+        if let Some(vlen) = options.vlen {
+            for (i, vwrite) in vwrites {
+                if vwrite.value.len() * 8 > vlen {
+                    bail!(
+                        "event {i} writes {} bits to v{} but VLEN is {vlen}",
+                        vwrite.value.len() * 8,
+                        vwrite.index
+                    );
+                }
+            }
+            cpu.vregs = vec![vec![0; vlen / 8]; 32];
+        } else if vwrites.count() > 0 {
+            warn!("The trace has vector register writes; set --vlen to see them");
+        }
+
+        let machine = Machine {
             exec_mode: ExecMode::Continue,
             exec_dir: ExecDir::Forwards,
 
+            options,
+
             cpu,
             mem,
 
             elf,
 
             entry,
+            initial_pc,
 
             trace,
             trace_index: 0,
 
             watchpoints: Vec::new(),
-            breakpoints: Vec::new(),
+            watched_regs: BTreeSet::new(),
+            breakpoints: BTreeSet::new(),
+            breakpoint_conditions: BTreeMap::new(),
+            breakpoint_ignore_counts: BTreeMap::new(),
             files: Vec::new(),
 
             tracepoints: BTreeMap::new(),
@@ -159,8 +362,41 @@ impl<A: RiscvArch> Machine<A> {
             tracing: false,
             selected_frame: None,
 
-            send_time,
-        })
+            last_read_tags: None,
+
+            access_log: VecDeque::new(),
+            access_log_truncated: false,
+
+            console_output: String::new(),
+
+            send_status,
+        };
+        machine.update_status(false);
+        Ok(machine)
+    }
+
+    /// The exit code if the trace ends with the program exiting.
+    ///
+    /// An exit is recognised by the last event in the trace being an `ecall`
+    /// with `a7` set to the `exit` (93) or `exit_group` (94) syscall number,
+    /// as used by newlib and Linux. The exit code is taken from `a0`.
+    fn exit_code(&self) -> Option<u8> {
+        const SYS_EXIT: u64 = 93;
+        const SYS_EXIT_GROUP: u64 = 94;
+
+        let last = self.trace.last()?;
+        let instruction = last.instruction?;
+        if !decode::matcher(instruction)(riscv_opcodes::MASK_ECALL, riscv_opcodes::MATCH_ECALL) {
+            return None;
+        }
+
+        let syscall = self.cpu.xregs[17].to_u64()?;
+        if syscall != SYS_EXIT && syscall != SYS_EXIT_GROUP {
+            return None;
+        }
+
+        // Only the low 8 bits of the exit status are visible to the parent.
+        Some(self.cpu.xregs[10].to_u64()? as u8)
     }
 
     /// Single-step the interpreter. Returns None if it wasn't stopped (no breakpoint etc.).
@@ -198,33 +434,50 @@ impl<A: RiscvArch> Machine<A> {
         let mut sniffer =
             MemSniffer::new(&mut self.mem, &tmp, |access| hit_watchpoint = Some(access));
 
-        match self.exec_dir {
+        // The event that was applied or undone.
+        let event_index = match self.exec_dir {
             ExecDir::Forwards => {
+                if self.trace.is_empty() {
+                    // Nothing ran, so it didn't exit or crash either.
+                    return Some(SingleThreadStopReason::ReplayLog {
+                        tid: None,
+                        pos: ReplayLogPosition::End,
+                    });
+                }
                 if self.trace_index >= self.trace.len() {
-                    return Some(SingleThreadStopReason::Terminated(Signal::SIGSTOP));
+                    return Some(match self.exit_code() {
+                        Some(code) => SingleThreadStopReason::Exited(code),
+                        None => SingleThreadStopReason::Terminated(Signal::SIGSTOP),
+                    });
                 }
                 self.cpu
                     .step(&mut sniffer, &mut self.trace[self.trace_index]);
                 self.trace_index += 1;
+                self.record_access(self.trace_index - 1);
+                self.trace_index - 1
             }
             ExecDir::Backwards => {
                 if self.trace_index == 0 {
-                    // TODO: Double check this.
-                    return Some(SingleThreadStopReason::DoneStep);
+                    // GDB reports "No more reverse-execution history". We
+                    // stay here until the debugger goes forwards again.
+                    return Some(SingleThreadStopReason::ReplayLog {
+                        tid: None,
+                        pos: ReplayLogPosition::Begin,
+                    });
                 }
                 self.trace_index -= 1;
-                let prev_event = if self.trace_index >= 1 && self.trace_index - 1 < self.trace.len()
-                {
-                    Some(&self.trace[self.trace_index - 1])
-                } else {
-                    None
-                };
+                let prev_pc = self
+                    .trace_index
+                    .checked_sub(1)
+                    .and_then(|i| self.trace.get(i))
+                    .map_or(self.initial_pc, |event| event.pc);
                 self.cpu
-                    .step_undo(&mut sniffer, &self.trace[self.trace_index], prev_event);
+                    .step_undo(&mut sniffer, &self.trace[self.trace_index], prev_pc);
+                self.trace_index
             }
-        }
+        };
 
-        if let Some(access) = hit_watchpoint {
+        if let Some(_access) = hit_watchpoint {
             // TODO: I think this is setting PC back to the previous instruction,
             // but do we need to actually reverse instruction too?
             // Also seeing as we already know the access address I think we
@@ -242,8 +495,64 @@ impl<A: RiscvArch> Machine<A> {
             // });
         }
 
-        if self.breakpoints.contains(&self.cpu.pc) {
-            return Some(SingleThreadStopReason::SwBreak(()));
+        if let Some(xwrite) = &self.trace[event_index].xwrite
+            && self.watched_regs.contains(&xwrite.index)
+            && let Some(prev_value) = xwrite.prev_value
+        {
+            let (from, to) = match self.exec_dir {
+                ExecDir::Forwards => (prev_value, xwrite.value),
+                ExecDir::Backwards => (xwrite.value, prev_value),
+            };
+            let message = format!(
+                "Register {} changed: {:#x} -> {:#x}",
+                GPR_ABI_NAMES[xwrite.index as usize],
+                from.to_u64().unwrap_or_default(),
+                to.to_u64().unwrap_or_default(),
+            );
+            info!("{message}");
+            self.console_output.push_str(&message);
+            self.console_output.push('\n');
+            return Some(SingleThreadStopReason::Signal(Signal::SIGTRAP));
+        }
+
+        if self.options.stop_on_trap
+            && let Some(event) = self
+                .trace_index
+                .checked_sub(1)
+                .and_then(|i| self.trace.get(i))
+            && event.trap
+        {
+            let csr = |num| self.cpu.csrs.get(&num).and_then(|val| val.to_u64());
+            let (mcause, mtval) = (csr(CSR_MCAUSE), csr(CSR_MTVAL));
+            let signal = self.options.trap_signals.signal(mcause, mtval);
+            let show =
+                |val: Option<u64>| val.map_or("unknown".to_owned(), |val| format!("{val:#x}"));
+            let message = format!(
+                "Trap at {:#x} (trace line {}, mcause: {}, mtval: {}): {signal}",
+                event.pc.to_u64().unwrap_or_default(),
+                event.line,
+                show(mcause),
+                show(mtval),
+            );
+            info!("{message}");
+            self.console_output.push_str(&message);
+            self.console_output.push('\n');
+            return Some(SingleThreadStopReason::Signal(signal));
+        }
+
+        if self.breakpoints.contains(&self.cpu.pc)
+            && self
+                .breakpoint_conditions
+                .get(&self.cpu.pc)
+                .is_none_or(|condition| condition.eval(&self.cpu))
+        {
+            match self.breakpoint_ignore_counts.get_mut(&self.cpu.pc) {
+                Some(1) => {
+                    self.breakpoint_ignore_counts.remove(&self.cpu.pc);
+                }
+                Some(count) => *count -= 1,
+                None => return Some(SingleThreadStopReason::SwBreak(())),
+            }
         }
 
         None
@@ -266,6 +575,7 @@ impl<A: RiscvArch> Machine<A> {
                     // will lead to increased latency.
                     if cycles % 1024 == 0 {
                         // Yield back to Tokio so other things can run.
+                        self.update_status(true);
                         yield_now().await;
                     }
                     cycles += 1;
@@ -284,6 +594,7 @@ impl<A: RiscvArch> Machine<A> {
                     // will lead to increased latency.
                     if cycles % 1024 == 0 {
                         // Yield back to Tokio so other things can run.
+                        self.update_status(true);
                         yield_now().await;
                     }
                     cycles += 1;
@@ -299,11 +610,456 @@ impl<A: RiscvArch> Machine<A> {
             }
         };
 
-        // Update the time. Ignore errors.
-        if let Some(event) = self.trace.get(self.trace_index) {
-            let _ = self.send_time.send(event.time);
-        }
+        self.update_status(false);
 
         event
     }
+
+    /// Find the tagged capability slots overlapping `[addr, addr + len)`.
+    pub fn read_tags(&self, addr: u64, len: u64) -> ReadTags {
+        let cap_size = 2 * size_of::<A::Usize>() as u64;
+        let end = addr.saturating_add(len);
+        let first_slot = addr - addr % cap_size;
+        let slots = (first_slot..end)
+            .step_by(cap_size as usize)
+            .filter(|slot| self.mem.tag(*slot))
+            .map(|slot| (slot, slot >= addr && slot + cap_size <= end))
+            .collect();
+        ReadTags { addr, len, slots }
+    }
+
+    /// The most recently applied event, i.e. the one at the current PC.
+    pub fn current_event(&self) -> Option<&TraceEvent<A::Usize>> {
+        self.trace_index
+            .checked_sub(1)
+            .and_then(|i| self.trace.get(i))
+    }
+
+    /// Add the store of the event at `index` (which has just been applied)
+    /// to the access log, unless it has already been recorded.
+    fn record_access(&mut self, index: usize) {
+        let Some(limit) = self.options.record_accesses.filter(|&limit| limit > 0) else {
+            return;
+        };
+        let event = &self.trace[index];
+        let Some(store) = &event.store else {
+            return;
+        };
+        if self
+            .access_log
+            .back()
+            .is_some_and(|last| last.index >= index)
+        {
+            // Going forwards again after reversing.
+            return;
+        }
+        if self.access_log.len() >= limit {
+            self.access_log.pop_front();
+            self.access_log_truncated = true;
+        }
+        self.access_log.push_back(AccessRecord {
+            index,
+            pc: event.pc.to_u64().unwrap_or_default(),
+            cycle: event.cycle,
+            addr: store.phys_addr,
+            size: store.value.size(),
+            value: store.value.to_u128(),
+        });
+    }
+
+    /// Jump straight to `index` in the trace, ignoring breakpoints etc.
+    /// The index is clamped to the length of the trace. Returns the new
+    /// index.
+    pub fn seek(&mut self, index: usize) -> usize {
+        let index = index.min(self.trace.len());
+
+        while self.trace_index < index {
+            self.cpu
+                .step(&mut self.mem, &mut self.trace[self.trace_index]);
+            self.trace_index += 1;
+            self.record_access(self.trace_index - 1);
+        }
+
+        while self.trace_index > index {
+            self.trace_index -= 1;
+            let prev_pc = self
+                .trace_index
+                .checked_sub(1)
+                .and_then(|i| self.trace.get(i))
+                .map_or(self.initial_pc, |event| event.pc);
+            self.cpu
+                .step_undo(&mut self.mem, &self.trace[self.trace_index], prev_pc);
+        }
+
+        self.update_status(false);
+
+        index
+    }
+
+    /// Step forwards through the rest of the trace, yielding the
+    /// reconstructed state after each event. This is for tools that want
+    /// the full reconstruction without going through GDB.
+    pub fn iter_states(&mut self) -> States<'_, A> {
+        States { machine: self }
+    }
+
+    /// Send the current position to anything that is listening (e.g.
+    /// Surfer).
+    fn update_status(&self, running: bool) {
+        // This must be the event the CPU state is from, not the next one
+        // to be applied, otherwise it is out by one after stepping in
+        // either direction.
+        let Some(event) = self.current_event().or_else(|| self.trace.first()) else {
+            return;
+        };
+        // Ignore errors.
+        let _ = self.send_status.send(ReplayStatus {
+            time: event.time,
+            cycle: event.cycle,
+            pc: self.cpu.pc.to_u64().unwrap_or_default(),
+            trace_index: self.trace_index,
+            trace_len: self.trace.len(),
+            reverse: matches!(self.exec_dir, ExecDir::Backwards),
+            running,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use riscv_opcodes::CSR_MSTATUS;
+
+    use super::*;
+    use crate::ibex_trace;
+    use crate::riscv::RiscvArch32;
+    use crate::test_util::{
+        cheriot_trace_machine, fixture, machine, trace_machine, try_machine,
+        try_machine_with_status,
+    };
+    use crate::trace::ReadOptions;
+
+    #[test]
+    fn tls_sections() {
+        let machine = machine::<RiscvArch32>("rv32.elf", Vec::new(), MachineOptions::default());
+
+        // `.tdata` is loaded as the initialisation image.
+        assert_eq!(machine.mem.r32(0x1000), 0x44332211);
+        // `.tbss` is at the same address as `.data` and mustn't clobber it.
+        assert_eq!(machine.mem.r32(0x1004), 0x88776655);
+    }
+
+    fn cheri_bounds_machine(trap_signals: TrapSignals) -> Machine<RiscvArch32> {
+        let options = MachineOptions {
+            stop_on_trap: true,
+            trap_signals,
+            ..Default::default()
+        };
+        cheriot_trace_machine("cheri_bounds.log", options)
+    }
+
+    fn step_until_stop(machine: &mut Machine<RiscvArch32>) -> SingleThreadStopReason<u32> {
+        loop {
+            if let Some(stop) = machine.step() {
+                return stop;
+            }
+        }
+    }
+
+    /// Step until the machine stops, returning the signal.
+    fn stop_signal(machine: &mut Machine<RiscvArch32>) -> Option<Signal> {
+        loop {
+            match machine.step() {
+                None => continue,
+                Some(SingleThreadStopReason::Signal(signal)) => return Some(signal),
+                Some(_) => return None,
+            }
+        }
+    }
+
+    #[test]
+    fn stop_on_trap_uses_configured_signal() {
+        let signals = TrapSignals::from_file(&fixture("trap_signals.txt")).unwrap();
+        let mut machine = cheri_bounds_machine(signals);
+
+        assert_eq!(stop_signal(&mut machine), Some(Signal::SIGUSR1));
+        assert_eq!(machine.trace_index, 2);
+        // The handler's reads of mcause and mtval are applied at the trap.
+        assert_eq!(machine.cpu.csrs.get(&CSR_MCAUSE), Some(&0x1c));
+        assert_eq!(machine.cpu.csrs.get(&CSR_MTVAL), Some(&0x01));
+        // GDB is told why it stopped.
+        assert_eq!(
+            machine.console_output,
+            "Trap at 0x2 (trace line 3, mcause: 0x1c, mtval: 0x1): SIGUSR1 - User defined signal 1\n"
+        );
+
+        machine.seek(1);
+        assert!(machine.cpu.csrs.is_empty());
+    }
+
+    #[test]
+    fn stop_on_trap_default_signal() {
+        let mut machine = cheri_bounds_machine(TrapSignals::default());
+        assert_eq!(stop_signal(&mut machine), Some(Signal::SIGSEGV));
+    }
+
+    #[test]
+    fn watched_register_change_is_sent_to_console() {
+        let mut machine = cheri_bounds_machine(TrapSignals::default());
+        machine.watched_regs.insert(10);
+
+        assert_eq!(stop_signal(&mut machine), Some(Signal::SIGTRAP));
+        assert_eq!(machine.console_output, "Register a0 changed: 0x0 -> 0x5\n");
+    }
+
+    #[test]
+    fn byte_store_clears_tag() {
+        let mut machine = cheriot_trace_machine("captag.log", Default::default());
+
+        machine.seek(1);
+        assert!(machine.mem.tag(0x100));
+        assert_eq!(machine.read_tags(0x100, 8).slots, [(0x100, true)]);
+
+        // Overwriting one byte of the capability leaves it untagged.
+        machine.seek(2);
+        assert!(!machine.mem.tag(0x100));
+        assert_eq!(machine.mem.r8(0x102), 0x55);
+        assert!(machine.read_tags(0x100, 8).slots.is_empty());
+
+        // Reversing the byte store restores the tag.
+        machine.seek(1);
+        assert!(machine.mem.tag(0x100));
+    }
+
+    #[test]
+    fn header_only_trace() {
+        let mut machine = trace_machine("header_only.log", Default::default());
+        assert!(machine.trace.is_empty());
+
+        // Neither direction has anything to replay, and it isn't an exit.
+        assert!(matches!(
+            machine.step(),
+            Some(SingleThreadStopReason::ReplayLog {
+                pos: ReplayLogPosition::End,
+                ..
+            })
+        ));
+        machine.exec_dir = ExecDir::Backwards;
+        assert!(matches!(
+            machine.step(),
+            Some(SingleThreadStopReason::ReplayLog {
+                pos: ReplayLogPosition::Begin,
+                ..
+            })
+        ));
+        assert_eq!(machine.trace_index, 0);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn status_time_after_reverse_step() {
+        let trace = ibex_trace::read_trace(&fixture("loop.log"), &Default::default()).unwrap();
+        let (mut machine, status) =
+            try_machine_with_status::<RiscvArch32>("rv32.elf", trace, Default::default()).unwrap();
+
+        machine.seek(5);
+        assert_eq!(status.borrow().time, machine.trace[4].time);
+
+        machine.exec_mode = ExecMode::Step;
+        machine.exec_dir = ExecDir::Backwards;
+        machine.run().await;
+        assert_eq!(machine.trace_index, 4);
+        // The time is the event whose state is shown, i.e. the one before.
+        let reported = *status.borrow();
+        assert_eq!(reported.time, machine.trace[3].time);
+        assert_eq!(reported.pc, u64::from(machine.trace[3].pc));
+        assert!(reported.reverse);
+
+        machine.exec_dir = ExecDir::Forwards;
+        machine.run().await;
+        assert_eq!(status.borrow().time, machine.trace[4].time);
+    }
+
+    fn amo_machine(record_accesses: Option<usize>) -> Machine<RiscvArch32> {
+        let options = MachineOptions {
+            record_accesses,
+            ..Default::default()
+        };
+        trace_machine("amo.log", options)
+    }
+
+    #[test]
+    fn access_log() {
+        let mut machine = amo_machine(Some(10));
+        machine.seek(machine.trace.len());
+        let stores: Vec<_> = machine
+            .access_log
+            .iter()
+            .map(|record| (record.index, record.addr, record.value))
+            .collect();
+        // The failed `sc.w` doesn't store.
+        assert_eq!(stores, [(2, 0xff0, 5), (3, 0xff0, 9)]);
+        assert!(!machine.access_log_truncated);
+
+        // Going forwards again after reversing doesn't record them twice.
+        machine.seek(0);
+        machine.seek(machine.trace.len());
+        assert_eq!(machine.access_log.len(), 2);
+    }
+
+    #[test]
+    fn reverse_over_amo() {
+        let mut machine = amo_machine(None);
+        machine.seek(4);
+        assert_eq!(machine.mem.r32(0xff0), 9);
+        assert_eq!(machine.cpu.xregs[10], 5);
+
+        // The failed `sc.w` leaves memory alone.
+        machine.step();
+        assert_eq!(machine.cpu.xregs[12], 1);
+        assert_eq!(machine.mem.r32(0xff0), 9);
+
+        machine.exec_dir = ExecDir::Backwards;
+        machine.step();
+        assert_eq!(machine.mem.r32(0xff0), 9);
+        // Undoing the `amoadd.w` restores the value from before the
+        // read-modify-write.
+        machine.step();
+        assert_eq!(machine.trace_index, 3);
+        assert_eq!(machine.mem.r32(0xff0), 5);
+        assert_eq!(machine.cpu.pc, 0x4);
+    }
+
+    #[test]
+    fn access_log_limit() {
+        let mut machine = amo_machine(Some(1));
+        machine.seek(machine.trace.len());
+        assert_eq!(machine.access_log.len(), 1);
+        assert_eq!(machine.access_log[0].index, 3);
+        assert!(machine.access_log_truncated);
+
+        let mut machine = amo_machine(Some(0));
+        machine.seek(machine.trace.len());
+        assert!(machine.access_log.is_empty());
+        assert!(!machine.access_log_truncated);
+    }
+
+    #[test]
+    fn overlapping_sections() {
+        let strict = MachineOptions {
+            strict: true,
+            ..Default::default()
+        };
+        assert!(try_machine::<RiscvArch32>("overlap.elf", Vec::new(), Default::default()).is_ok());
+        let err = try_machine::<RiscvArch32>("overlap.elf", Vec::new(), strict.clone())
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("overlaps section"), "{err}");
+
+        assert!(try_machine::<RiscvArch32>("empty_section.elf", Vec::new(), strict).is_ok());
+    }
+
+    #[test]
+    fn initial_pc_is_first_event() {
+        let replay = trace_machine("loop.log", Default::default());
+        assert_eq!(replay.entry, 0x20);
+        assert_eq!(replay.cpu.pc, replay.trace[0].pc);
+
+        // With no trace it falls back to the entry point.
+        let replay = machine::<RiscvArch32>("rv32.elf", Vec::new(), Default::default());
+        assert_eq!(replay.cpu.pc, 0x20);
+    }
+
+    #[test]
+    fn init_regs() {
+        let options = MachineOptions {
+            init_regs: Some(fixture("init_regs.txt")),
+            ..Default::default()
+        };
+        let mut machine = trace_machine("loop.log", options);
+        assert_eq!(machine.cpu.pc, 0x80);
+        assert_eq!(machine.cpu.xregs[2], 0x2000);
+        assert_eq!(machine.cpu.xregs[10], 5);
+        assert_eq!(machine.cpu.csrs.get(&CSR_MSTATUS), Some(&0x1880));
+
+        // Going back to the start restores the initial PC, not the first
+        // event's.
+        machine.seek(3);
+        assert_eq!(machine.cpu.xregs[2], 0xff0);
+        machine.seek(0);
+        assert_eq!(machine.cpu.pc, 0x80);
+        assert_eq!(machine.cpu.xregs[2], 0x2000);
+
+        machine.seek(1);
+        machine.exec_dir = ExecDir::Backwards;
+        machine.step();
+        assert_eq!(machine.trace_index, 0);
+        assert_eq!(machine.cpu.pc, 0x80);
+    }
+
+    #[test]
+    fn max_events() {
+        let options = ReadOptions {
+            max_events: Some(4),
+            ..Default::default()
+        };
+        let trace = ibex_trace::read_trace(&fixture("loop.log"), &options).unwrap();
+        let mut machine = machine::<RiscvArch32>("rv32.elf", trace, Default::default());
+        assert_eq!(machine.trace.len(), 4);
+
+        // The full trace exits but the truncated one just ends.
+        assert!(matches!(
+            step_until_stop(&mut machine),
+            SingleThreadStopReason::Terminated(Signal::SIGSTOP)
+        ));
+        assert_eq!(machine.cpu.pc, 0x6);
+        assert_eq!(machine.current_event().unwrap().cycle, 4);
+    }
+
+    #[test]
+    fn exit_ecall() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        assert!(matches!(
+            step_until_stop(&mut machine),
+            SingleThreadStopReason::Exited(0)
+        ));
+
+        // An ecall that isn't `exit` or `exit_group` at the end of the trace
+        // isn't an exit.
+        let mut machine = trace_machine("loop.log", Default::default());
+        let write = machine.trace[13].xwrite.as_mut().unwrap();
+        assert_eq!((write.index, write.value), (17, 93));
+        write.value = 64;
+        assert!(matches!(
+            step_until_stop(&mut machine),
+            SingleThreadStopReason::Terminated(Signal::SIGSTOP)
+        ));
+    }
+
+    #[test]
+    fn iter_states() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        let len = machine.trace.len();
+
+        let mut states = machine.iter_states();
+        let mut indexes = Vec::new();
+        let mut stored = None;
+        while let Some((index, cpu, mem)) = states.next_state() {
+            indexes.push(index);
+            if index == 2 {
+                stored = Some((cpu.xregs[10], mem.r8(0xff0)));
+            }
+        }
+        assert_eq!(indexes, (0..len).collect::<Vec<_>>());
+        // The state is after the event, e.g. the store is visible.
+        assert_eq!(stored, Some((5, 5)));
+        assert_eq!(machine.trace_index, len);
+
+        // It carries on from the current position.
+        machine.seek(len - 2);
+        let mut states = machine.iter_states();
+        let mut count = 0;
+        while states.next_state().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 2);
+    }
 }
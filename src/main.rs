@@ -1,18 +1,7 @@
 //! A simple gdbserver implementation for RISC-V trace files.
 
-mod buffered_connection;
-mod cheriot_ibex_trace;
-mod cpu;
-mod gdb;
-mod ibex_trace;
-mod logging;
-mod machine;
-mod mem_sniffer;
-mod memory;
-mod riscv;
-mod trace;
-
 use anyhow::Context as _;
+use anyhow::anyhow;
 use anyhow::bail;
 use gdbstub::common::Signal;
 use gdbstub::stub::DisconnectReason;
@@ -22,8 +11,17 @@ use gdbstub::stub::state_machine;
 use log::error;
 use log::info;
 
+use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt as _;
+use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt as _;
+use tokio::net::TcpListener;
+use tokio::net::TcpSocket;
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::process::Command;
 use tokio::select;
 use tokio::sync::watch;
@@ -32,22 +30,55 @@ use tokio::sync::watch::Sender;
 
 use anyhow::Result;
 use clap::Parser;
+use std::io::Read as _;
+use std::net::Ipv4Addr;
+use std::path::Path;
 use std::path::PathBuf;
 
-use crate::buffered_connection::BufferedConnection;
-use crate::riscv::RiscvArch;
-use crate::riscv::RiscvArch32;
-use crate::riscv::RiscvArch64;
-use crate::trace::TraceEvent;
+use riscv_trace_debugger::buffered_connection::BufferedConnection;
+use riscv_trace_debugger::check;
+use riscv_trace_debugger::cheriot_ibex_trace;
+use riscv_trace_debugger::ibex_trace;
+use riscv_trace_debugger::logging;
+use riscv_trace_debugger::machine;
+use riscv_trace_debugger::machine::MachineOptions;
+use riscv_trace_debugger::machine::ReplayStatus;
+use riscv_trace_debugger::riscv::RiscvArch;
+use riscv_trace_debugger::riscv::RiscvArch32;
+use riscv_trace_debugger::riscv::RiscvArch64;
+use riscv_trace_debugger::trace::ReadOptions;
+use riscv_trace_debugger::trace::TraceColumns;
+use riscv_trace_debugger::trace::TraceEvent;
+use riscv_trace_debugger::trap::TrapSignals;
+
+#[cfg(feature = "tui")]
+mod tui;
+
+const DEFAULT_PORT: u16 = 9001;
+
+/// Size of the GDB packet buffer, which is advertised as `PacketSize`. The
+/// default is 4 KiB, which means GDB splits large memory reads into lots of
+/// 2 KiB packets.
+const PACKET_BUFFER_SIZE: usize = 64 * 1024;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 struct Args {
-    // TODO: Add UDS support back, maybe.
-    // /// Use UNIX domain socket instead of TCP.
-    // #[arg(long, value_name = "SOCKET_PATH")]
-    // uds: Option<PathBuf>,
-    /// Path to the ELF file
+    /// TCP port to listen on for the GDB connection. Defaults to 9001, unless
+    /// `--uds` is given. If both are given, whichever connects first is used.
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// Maximum number of pending TCP connections.
+    #[arg(long, value_name = "N", default_value_t = 1024)]
+    backlog: u32,
+
+    /// Path of a UNIX domain socket to listen on for the GDB connection.
+    #[cfg(unix)]
+    #[arg(long, value_name = "SOCKET_PATH")]
+    uds: Option<PathBuf>,
+
+    /// Path to the ELF file. This may be zstd or gzip compressed.
     #[arg(long, value_name = "ELF_PATH")]
     elf: PathBuf,
 
@@ -62,6 +93,109 @@ struct Args {
     /// Path to a waves file to open with Surfer (VCD or FST).
     #[arg(long, value_name = "WAVE_FILE")]
     waves: Option<PathBuf>,
+
+    /// Only read the first N events from the trace file.
+    #[arg(long, value_name = "N")]
+    max_events: Option<usize>,
+
+    /// Which tab-separated column each field of the trace is in, for traces
+    /// that don't use the Ibex layout, e.g. `time=0,cycle=1,pc=2,insn=3,accesses=6`.
+    /// `time`, `cycle`, `pc` and `insn` are required. `mnemonic`, `args` and
+    /// `accesses` are optional.
+    #[arg(long, value_name = "SPEC")]
+    trace_columns: Option<TraceColumns>,
+
+    /// Keep the original text of each trace line so it can be shown with
+    /// `monitor rawline`. This uses a lot more memory for large traces.
+    #[arg(long)]
+    keep_raw: bool,
+
+    /// Decode the mnemonic of instructions when the trace doesn't have one
+    /// (e.g. there is no mnemonic column). Decoded instructions have
+    /// `(decoded)` as their arguments.
+    #[arg(long)]
+    decode: bool,
+
+    /// Check the trace for inconsistencies (e.g. wrong return addresses)
+    /// and exit instead of waiting for a debugger.
+    #[arg(long)]
+    check: bool,
+
+    /// Print the `--check` report as JSON.
+    #[arg(long, requires = "check")]
+    json: bool,
+
+    /// File of monitor commands (one per line) to run before waiting for a
+    /// debugger, e.g. `seek 1000` then `check-regs expected.txt`.
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
+
+    /// Exit after running `--script` instead of waiting for a debugger.
+    #[arg(long, requires = "script")]
+    batch: bool,
+
+    /// Allow the debugger to write memory. Writes are kept in an overlay
+    /// that shadows the reconstructed memory without affecting the replay.
+    /// Use `monitor clear-overlay` to discard them.
+    #[arg(long)]
+    allow_writes: bool,
+
+    /// Serve files from this directory to the debugger via host I/O. Paths
+    /// are relative to it and can't escape it. Without this, host I/O can
+    /// access any file.
+    #[arg(long, value_name = "DIR")]
+    host_io_root: Option<PathBuf>,
+
+    /// Stop when a trap is executed. The signal reported to the debugger
+    /// depends on `mcause` (and for CHERI faults, `mtval`), which are taken
+    /// from the trap handler reading them.
+    #[arg(long)]
+    stop_on_trap: bool,
+
+    /// File overriding the signals reported for each trap cause, with lines
+    /// like `mcause:2=SIGILL` or `cheri:0x01=SIGSEGV`.
+    #[arg(long, value_name = "FILE")]
+    trap_signals: Option<PathBuf>,
+
+    /// Vector register length in bits (a power of two, at least 8). Vector
+    /// register writes in the trace (`v<n>=0x...`) are only shown if this is
+    /// set.
+    #[arg(long, value_name = "BITS")]
+    vlen: Option<usize>,
+
+    /// Absolute path that the ELF is served at to the debugger (via host
+    /// I/O and `qXfer:exec-file`). The default is `/test.elf`.
+    #[arg(long, value_name = "PATH")]
+    exec_name: Option<String>,
+
+    /// Record every memory store as it is replayed so `monitor access-log
+    /// <addr>` can show the writes to an address. At most N stores are kept
+    /// (default 1000000).
+    #[arg(
+        long,
+        value_name = "N",
+        num_args = 0..=1,
+        default_missing_value = "1000000",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    record_accesses: Option<usize>,
+
+    /// Register dump (`name=value` lines, like `check-regs`) of the
+    /// registers when the trace starts, for traces that don't start from
+    /// reset. `pc` overrides the PC of the first event.
+    #[arg(long, value_name = "FILE")]
+    init_regs: Option<PathBuf>,
+
+    /// Fail if the ELF looks wrong (e.g. it has overlapping sections)
+    /// instead of just warning.
+    #[arg(long)]
+    strict: bool,
+
+    /// Show a status panel with the replay position while a debugger is
+    /// connected.
+    #[cfg(feature = "tui")]
+    #[arg(long)]
+    tui: bool,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -69,7 +203,7 @@ async fn main() -> Result<()> {
     logging::init_logging()?;
 
     let args = Args::parse();
-    let elf = std::fs::read(&args.elf)?;
+    let elf = read_elf(&args.elf)?;
 
     let elf_header = goblin::elf::Elf::parse(&elf)?;
 
@@ -84,104 +218,246 @@ async fn main() -> Result<()> {
     //     bail!("Not a RISC-V ELF");
     // }
 
-    if elf_header.is_64 {
+    let result = if elf_header.is_64 {
         info!("64-bit ELF");
         main_impl::<RiscvArch64>(args, elf).await
     } else {
         info!("32-bit ELF");
         main_impl::<RiscvArch32>(args, elf).await
+    };
+
+    // Errors are printed after this so make sure any log messages (which
+    // may be going to a file) come first.
+    log::logger().flush();
+    result
+}
+
+/// Read the ELF, decompressing it if it is zstd or gzip compressed.
+fn read_elf(path: &Path) -> Result<Vec<u8>> {
+    const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+    const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+    let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+
+    let mut elf = Vec::new();
+    if data.starts_with(ZSTD_MAGIC) {
+        info!("Decompressing zstd ELF");
+        ruzstd::decoding::StreamingDecoder::new(data.as_slice())
+            .map_err(|e| anyhow!("decompressing {}: {e}", path.display()))?
+            .read_to_end(&mut elf)
+            .with_context(|| format!("decompressing {}", path.display()))?;
+    } else if data.starts_with(GZIP_MAGIC) {
+        info!("Decompressing gzip ELF");
+        flate2::read::GzDecoder::new(data.as_slice())
+            .read_to_end(&mut elf)
+            .with_context(|| format!("decompressing {}", path.display()))?;
+    } else {
+        elf = data;
     }
+    Ok(elf)
 }
 
 async fn main_impl<A: RiscvArch>(args: Args, elf: Vec<u8>) -> Result<()> {
-    let (send_time, receive_time) = watch::channel(0);
+    let (send_status, receive_status) = watch::channel(ReplayStatus::default());
 
     if let Some(waves) = &args.waves {
         let waves = waves.to_owned();
         // Start the task to spawn Surfer and connect to us.
         tokio::task::spawn(async {
-            if let Err(e) = main_waves(waves, receive_time).await {
+            if let Err(e) = main_waves(waves, receive_status).await {
                 error!("{e:?}");
             }
         });
     }
 
-    main_gdb::<A>(args, elf, send_time).await
+    main_gdb::<A>(args, elf, send_status).await
 }
 
-async fn main_gdb<A: RiscvArch>(args: Args, elf: Vec<u8>, send_time: Sender<u64>) -> Result<()> {
-    let trace: Vec<TraceEvent<A::Usize>> = match (args.ibex_trace, args.cheriot_ibex_trace) {
-        (Some(path), None) => ibex_trace::read_trace(&path),
-        (None, Some(path)) => cheriot_ibex_trace::read_trace(&path),
+async fn main_gdb<A: RiscvArch>(
+    args: Args,
+    elf: Vec<u8>,
+    send_status: Sender<ReplayStatus>,
+) -> Result<()> {
+    let read_options = ReadOptions {
+        max_events: args.max_events,
+        columns: args.trace_columns.clone().unwrap_or_default(),
+        keep_raw: args.keep_raw,
+        decode: args.decode,
+    };
+
+    let trace: Vec<TraceEvent<A::Usize>> = match (&args.ibex_trace, &args.cheriot_ibex_trace) {
+        (Some(path), None) => ibex_trace::read_trace(path, &read_options),
+        (None, Some(path)) => cheriot_ibex_trace::read_trace(path, &read_options),
         _ => bail!("Please provide exactly one trace file."),
     }?;
 
+    if args.check {
+        if trace.is_empty() {
+            bail!("The trace has no events to check");
+        }
+        let problems = check::check_trace(&trace);
+        if args.json {
+            println!("{}", check::json_report(&trace, &problems));
+        } else {
+            for problem in &problems {
+                println!("{problem}");
+            }
+        }
+        if !problems.is_empty() {
+            // This is an expected result rather than an error, so just give
+            // the summary and fail without the error's backtrace.
+            log::logger().flush();
+            eprintln!("{} problem(s) found in the trace", problems.len());
+            std::process::exit(1);
+        }
+        if !args.json {
+            println!("No problems found in {} events", trace.len());
+        }
+        return Ok(());
+    }
+
+    let host_io_root = match &args.host_io_root {
+        Some(root) => Some(
+            root.canonicalize()
+                .with_context(|| format!("resolving host I/O root {}", root.display()))?,
+        ),
+        None => None,
+    };
+
+    let trap_signals = match &args.trap_signals {
+        Some(path) => TrapSignals::from_file(path)?,
+        None => TrapSignals::default(),
+    };
+
+    if let Some(vlen) = args.vlen
+        && (vlen < 8 || !vlen.is_power_of_two())
+    {
+        bail!("--vlen must be a power of two and at least 8, got {vlen}");
+    }
+
+    if let Some(name) = &args.exec_name
+        && (!name.starts_with('/') || name.len() < 2)
+    {
+        bail!("--exec-name must be an absolute path, got {name:?}");
+    }
+
+    let options = MachineOptions {
+        allow_writes: args.allow_writes,
+        host_io_root,
+        stop_on_trap: args.stop_on_trap,
+        trap_signals,
+        vlen: args.vlen,
+        exec_name: args.exec_name.clone(),
+        strict: args.strict,
+        record_accesses: args.record_accesses,
+        init_regs: args.init_regs.clone(),
+        trace_format: if args.cheriot_ibex_trace.is_some() {
+            "Cheriot-Ibex"
+        } else {
+            "Ibex"
+        },
+    };
+
+    // The script only runs for the first connection, not after GDB reconnects.
+    let mut script = args.script.as_deref();
     let mut done = false;
 
     while !done {
         done = true;
 
-        let mut machine =
-            machine::Machine::<A>::new(elf.clone(), trace.clone(), send_time.clone())?;
+        let mut machine = machine::Machine::<A>::new(
+            elf.clone(),
+            trace.clone(),
+            options.clone(),
+            send_status.clone(),
+        )?;
 
-        let listener = tokio::net::TcpListener::bind("127.0.0.1:9001").await?;
+        if let Some(script) = script.take() {
+            run_script(&mut machine, script)?;
+            if args.batch {
+                return Ok(());
+            }
+        }
 
-        info!("Listening for GDB connection on 127.0.0.1:9001...");
+        let mut socket = wait_for_gdb(&args).await?;
 
-        // Accept a connection.
-        let (mut socket, _) = listener.accept().await?;
+        #[cfg(feature = "tui")]
+        let tui = args.tui.then(|| tui::Tui::spawn(send_status.subscribe()));
 
         let connection = BufferedConnection::default();
 
-        let gdb = GdbStub::new(connection);
+        let gdb = GdbStub::builder(connection)
+            .packet_buffer_size(PACKET_BUFFER_SIZE)
+            .build()?;
 
         let mut gdb = gdb.run_state_machine(&mut machine)?;
-        let disconnect_reason: Result<DisconnectReason> = loop {
-            gdb = match gdb {
-                state_machine::GdbStubStateMachine::Idle(mut gdb) => {
-                    // Flush any data to be sent.
-                    gdb.borrow_conn().flush(&mut socket).await?;
-
-                    // Wait for data from the GDB client.
-                    // TODO: What does read_u8 do on disconnection?
-                    let byte = socket.read_u8().await?;
-                    gdb.incoming_data(&mut machine, byte)?
-                }
+        // In a block so that the TUI is stopped even if there is an error.
+        let disconnect_reason: Result<DisconnectReason> = async {
+            loop {
+                gdb = match gdb {
+                    state_machine::GdbStubStateMachine::Idle(mut gdb) => {
+                        // Flush any data to be sent.
+                        gdb.borrow_conn().flush(&mut socket).await?;
+
+                        // Wait for data from the GDB client. If it goes away
+                        // without detaching treat it like a disconnection.
+                        let byte = match socket.read_u8().await {
+                            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                                break Ok(DisconnectReason::Disconnect);
+                            }
+                            byte => byte?,
+                        };
+                        gdb.incoming_data(&mut machine, byte)?
+                    }
 
-                state_machine::GdbStubStateMachine::Disconnected(mut gdb) => {
-                    // Flush any data to be sent.
-                    gdb.borrow_conn().flush(&mut socket).await?;
+                    state_machine::GdbStubStateMachine::Disconnected(mut gdb) => {
+                        // Flush any data to be sent, e.g. the exit reply, and
+                        // close our side so the client sees all of it. The
+                        // client may have closed already, which is fine.
+                        gdb.borrow_conn().flush(&mut socket).await?;
+                        let _ = socket.shutdown().await;
 
-                    // We're going to restart the whole process on disconnection.
-                    break Ok(gdb.get_reason());
-                }
+                        // We're going to restart the whole process on disconnection.
+                        break Ok(gdb.get_reason());
+                    }
 
-                state_machine::GdbStubStateMachine::CtrlCInterrupt(mut gdb) => {
-                    // Flush any data to be sent.
-                    gdb.borrow_conn().flush(&mut socket).await?;
+                    state_machine::GdbStubStateMachine::CtrlCInterrupt(mut gdb) => {
+                        // Flush any data to be sent.
+                        gdb.borrow_conn().flush(&mut socket).await?;
 
-                    // Stop on Ctrl-C.
-                    let stop_reason = Some(SingleThreadStopReason::Signal(Signal::SIGINT));
-                    gdb.interrupt_handled(&mut machine, stop_reason)?
-                }
-
-                state_machine::GdbStubStateMachine::Running(mut gdb) => {
-                    // Flush any data to be sent.
-                    gdb.borrow_conn().flush(&mut socket).await?;
+                        // Stop on Ctrl-C.
+                        let stop_reason = Some(SingleThreadStopReason::Signal(Signal::SIGINT));
+                        gdb.interrupt_handled(&mut machine, stop_reason)?
+                    }
 
-                    // Wait for a byte from the client, and a break in the simulation.
-                    select! {
-                        // TODO: What does read_u8 do on disconnection?
-                        byte = socket.read_u8() => {
-                            gdb.incoming_data(&mut machine, byte?)?
-                        }
-                        stop_reason = machine.run() => {
-                            gdb.report_stop(&mut machine, stop_reason)?
+                    state_machine::GdbStubStateMachine::Running(mut gdb) => {
+                        // Flush any data to be sent.
+                        gdb.borrow_conn().flush(&mut socket).await?;
+
+                        // Wait for a byte from the client, and a break in the simulation.
+                        select! {
+                            // TODO: What does read_u8 do on disconnection?
+                            byte = socket.read_u8() => {
+                                gdb.incoming_data(&mut machine, byte?)?
+                            }
+                            stop_reason = machine.run() => {
+                                let output = std::mem::take(&mut machine.console_output);
+                                if !output.is_empty() {
+                                    gdb.borrow_conn().console_output(&output);
+                                }
+                                gdb.report_stop(&mut machine, stop_reason)?
+                            }
                         }
                     }
                 }
             }
-        };
+        }
+        .await;
+
+        #[cfg(feature = "tui")]
+        if let Some(tui) = tui {
+            tui.stop().await;
+        }
 
         match disconnect_reason? {
             // VSCode's "Restart" is really disconnect and reattach
@@ -204,9 +480,125 @@ async fn main_gdb<A: RiscvArch>(args: Args, elf: Vec<u8>, send_time: Sender<u64>
     Ok(())
 }
 
-async fn main_waves(waves: PathBuf, mut receive_time: Receiver<u64>) -> Result<()> {
+/// Run the monitor commands in `path`, printing their output. Blank lines
+/// and lines starting with `#` are ignored.
+fn run_script<A: RiscvArch>(machine: &mut machine::Machine<A>, path: &Path) -> Result<()> {
+    let script =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let mut output = String::new();
+    run_script_commands(machine, &script, &mut output)?;
+    print!("{output}");
+    Ok(())
+}
+
+/// Run each command in `script`, writing it and its output to `out`.
+fn run_script_commands<A: RiscvArch>(
+    machine: &mut machine::Machine<A>,
+    script: &str,
+    out: &mut impl std::fmt::Write,
+) -> std::fmt::Result {
+    for line in script.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        writeln!(out, "> {line}")?;
+        machine.monitor_command(line, out);
+    }
+    Ok(())
+}
+
+/// A connection to the GDB client.
+trait GdbSocket: AsyncRead + AsyncWrite + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> GdbSocket for T {}
+
+/// Wait for a GDB client to connect over TCP and/or a UNIX domain socket
+/// according to `--port` and `--uds`. If both are enabled then the first
+/// client to connect is used and the other listener is closed.
+async fn wait_for_gdb(args: &Args) -> Result<Box<dyn GdbSocket>> {
+    #[cfg(unix)]
+    if let Some(uds) = &args.uds {
+        return Ok(match args.port {
+            Some(port) => select! {
+                socket = wait_for_tcp(port, args.backlog) => Box::new(socket?),
+                socket = wait_for_uds(uds) => Box::new(socket?),
+            },
+            None => Box::new(wait_for_uds(uds).await?),
+        });
+    }
+
+    Ok(Box::new(
+        wait_for_tcp(args.port.unwrap_or(DEFAULT_PORT), args.backlog).await?,
+    ))
+}
+
+/// Listen on `127.0.0.1:port`.
+fn bind_tcp(port: u16, backlog: u32) -> std::io::Result<TcpListener> {
+    let socket = TcpSocket::new_v4()?;
+    // Otherwise restarting quickly fails while the previous
+    // connection is in TIME_WAIT.
+    socket.set_reuseaddr(true)?;
+    socket.bind((Ipv4Addr::LOCALHOST, port).into())?;
+    socket.listen(backlog)
+}
+
+async fn wait_for_tcp(port: u16, backlog: u32) -> Result<TcpStream> {
+    let listener =
+        bind_tcp(port, backlog).with_context(|| format!("binding to 127.0.0.1:{port}"))?;
+
+    info!("Listening for GDB connection on 127.0.0.1:{port}...");
+
+    // Accept a connection.
+    let (socket, _) = listener.accept().await?;
+    Ok(socket)
+}
+
+#[cfg(unix)]
+async fn wait_for_uds(path: &Path) -> Result<UnixStream> {
+    use std::os::unix::fs::FileTypeExt as _;
+
+    /// Deletes the socket file when dropped, including if we are cancelled
+    /// because a TCP client connected first.
+    struct RemoveSocketFile<'a>(&'a Path);
+
+    impl Drop for RemoveSocketFile<'_> {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(self.0);
+        }
+    }
+
+    let listener = match UnixListener::bind(path) {
+        // If we were killed while waiting last time the socket file will
+        // still be there. It's only safe to delete it if nothing is
+        // listening.
+        Err(e)
+            if e.kind() == std::io::ErrorKind::AddrInUse
+                && std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket())
+                && UnixStream::connect(path)
+                    .await
+                    .is_err_and(|e| e.kind() == std::io::ErrorKind::ConnectionRefused) =>
+        {
+            info!("Removing stale socket {}", path.display());
+            std::fs::remove_file(path)
+                .with_context(|| format!("removing stale socket {}", path.display()))?;
+            UnixListener::bind(path)
+        }
+        listener => listener,
+    }
+    .with_context(|| format!("binding to {}", path.display()))?;
+    let _remove = RemoveSocketFile(path);
+
+    info!("Listening for GDB connection on {}...", path.display());
+
+    // Accept a connection. The socket file isn't needed after this.
+    let (socket, _) = listener.accept().await?;
+    Ok(socket)
+}
+
+async fn main_waves(waves: PathBuf, mut receive_status: Receiver<ReplayStatus>) -> Result<()> {
     // Start TCP server on random port.
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
     let port = listener.local_addr()?.port();
 
     // Verify we can run surfer successfully.
@@ -242,6 +634,7 @@ async fn main_waves(waves: PathBuf, mut receive_time: Receiver<u64>) -> Result<(
     socket.write_all(&[0]).await?;
 
     let mut read_buf = [0u8; 4096];
+    let mut last_time = None;
 
     // Listen for incoming data (which is discarded), receive_time events,
     // and for surfer to exit.
@@ -256,9 +649,16 @@ async fn main_waves(waves: PathBuf, mut receive_time: Receiver<u64>) -> Result<(
 
                 // TODO: Ideally we would process the responses.
             }
-            changed = receive_time.changed() => {
+            changed = receive_status.changed() => {
                 changed?;
-                let time = *receive_time.borrow_and_update();
+                let status = *receive_status.borrow_and_update();
+
+                // Only follow the position once it has settled.
+                if status.running || last_time == Some(status.time) {
+                    continue;
+                }
+                let time = status.time;
+                last_time = Some(time);
 
                 // Move cursor.
                 let message = format!(r#"{{
@@ -288,3 +688,84 @@ async fn main_waves(waves: PathBuf, mut receive_time: Receiver<u64>) -> Result<(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use riscv_trace_debugger::test_util::{fixture, trace_machine};
+
+    use super::*;
+
+    #[test]
+    fn read_compressed_elf() {
+        let plain = read_elf(&fixture("rv32.elf")).unwrap();
+        assert_eq!(plain, std::fs::read(fixture("rv32.elf")).unwrap());
+        assert_eq!(read_elf(&fixture("rv32.elf.zst")).unwrap(), plain);
+        assert_eq!(read_elf(&fixture("rv32.elf.gz")).unwrap(), plain);
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn rebind_tcp_port() {
+        let listener = bind_tcp(0, 1).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Close the connection from the server end first so its port is
+        // left in TIME_WAIT, like it is after GDB disconnects.
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        drop(server);
+        drop(client);
+        drop(listener);
+
+        bind_tcp(addr.port(), 1).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "current_thread")]
+    async fn stale_socket_is_replaced() {
+        let path = std::env::temp_dir().join(format!("rtd-socket-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // A socket file left behind with nothing listening on it.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let connect = async {
+            loop {
+                match UnixStream::connect(&path).await {
+                    Ok(stream) => break stream,
+                    Err(_) => tokio::task::yield_now().await,
+                }
+            }
+        };
+        let (server, _client) = tokio::join!(wait_for_uds(&path), connect);
+        server.unwrap();
+        // It is removed once GDB has connected.
+        assert!(!path.exists());
+
+        // A socket that is in use is left alone.
+        let listener = UnixListener::bind(&path).unwrap();
+        let err = wait_for_uds(&path).await.unwrap_err();
+        assert!(format!("{err:#}").contains("binding to"), "{err:#}");
+        assert!(path.exists());
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn script_seeks_then_dumps_state() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        let mut output = String::new();
+        run_script_commands(
+            &mut machine,
+            "# Go to the store.\nseek 3\n\nwhere\n",
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            "> seek 3\nAt index 3 of 15\n\
+             > where\nEvent 2 of 15 (trace line 4): pc 0x4, cycle 3, time 30: c.sw x10,0(x2)\n"
+        );
+        assert_eq!(machine.trace_index, 3);
+    }
+}
@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+
 use crate::memory::Memory;
 
 pub enum AccessKind {
@@ -5,13 +7,12 @@ pub enum AccessKind {
     Write,
 }
 
+// allow(dead_code) because watchpoints aren't hooked up yet.
+#[allow(dead_code)]
 pub struct Access {
     pub kind: AccessKind,
     pub addr: u64,
-    // allow(dead_code) because the emulator is so simple that it doesn't matter
-    #[allow(dead_code)]
     pub val: u64,
-    #[allow(dead_code)]
     pub len: usize,
 }
 
@@ -20,7 +21,8 @@ pub struct Access {
 pub struct MemSniffer<'a, M, F: FnMut(Access)> {
     mem: &'a mut M,
     addrs: &'a [u64],
-    on_access: F,
+    // In a `RefCell` because reads only have `&self`.
+    on_access: RefCell<F>,
 }
 
 impl<'a, M: Memory, F: FnMut(Access)> MemSniffer<'a, M, F> {
@@ -28,17 +30,17 @@ impl<'a, M: Memory, F: FnMut(Access)> MemSniffer<'a, M, F> {
         MemSniffer {
             mem,
             addrs,
-            on_access,
+            on_access: RefCell::new(on_access),
         }
     }
 }
 
 macro_rules! impl_memsniff_r {
     ($fn:ident, $ret:ty) => {
-        fn $fn(&mut self, addr: u64) -> $ret {
+        fn $fn(&self, addr: u64) -> $ret {
             let ret = self.mem.$fn(addr);
             if self.addrs.contains(&addr) {
-                (self.on_access)(Access {
+                (self.on_access.borrow_mut())(Access {
                     kind: AccessKind::Read,
                     addr,
                     val: ret as u64,
@@ -55,7 +57,7 @@ macro_rules! impl_memsniff_w {
         fn $fn(&mut self, addr: u64, val: $val) {
             self.mem.$fn(addr, val);
             if self.addrs.contains(&addr) {
-                (self.on_access)(Access {
+                (self.on_access.get_mut())(Access {
                     kind: AccessKind::Write,
                     addr,
                     val: val as u64,
@@ -77,4 +79,61 @@ impl<M: Memory, F: FnMut(Access)> Memory for MemSniffer<'_, M, F> {
     impl_memsniff_w!(w32, u32);
     impl_memsniff_w!(w64, u64);
     impl_memsniff_w!(w128, u128);
+
+    fn tag(&self, slot: u64) -> bool {
+        self.mem.tag(slot)
+    }
+
+    fn set_tag(&mut self, slot: u64, tag: bool) {
+        self.mem.set_tag(slot, tag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::SimpleMemory;
+
+    /// Reads only need a shared reference.
+    fn read_word(mem: &impl Memory, addr: u64) -> u32 {
+        mem.r32(addr)
+    }
+
+    #[test]
+    fn reads_through_shared_reference() {
+        let mut mem = SimpleMemory::default();
+        mem.w32(0x100, 0x12345678);
+
+        let view = &mem;
+        assert_eq!(read_word(view, 0x100), 0x12345678);
+        assert_eq!(view.r16(0x102), 0x1234);
+        assert_eq!(view.r8(0x100), 0x78);
+    }
+
+    #[test]
+    fn sniffer_sees_reads_and_writes() {
+        let mut mem = SimpleMemory::default();
+        let mut accesses = Vec::new();
+        let watched = [0x100];
+        {
+            let mut sniffer = MemSniffer::new(&mut mem, &watched, |access| {
+                accesses.push((
+                    matches!(access.kind, AccessKind::Write),
+                    access.addr,
+                    access.val,
+                    access.len,
+                ))
+            });
+
+            sniffer.w16(0x100, 0xabcd);
+            sniffer.w8(0x104, 1);
+            assert_eq!(read_word(&sniffer, 0x100), 0xabcd);
+        }
+
+        assert_eq!(
+            accesses,
+            [(true, 0x100, 0xabcd, 2), (false, 0x100, 0xabcd, 4)]
+        );
+        assert_eq!(mem.r8(0x104), 1);
+    }
 }
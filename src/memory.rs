@@ -1,16 +1,17 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
 
 pub trait Memory {
     /// Read a 8-bit value from `addr`
-    fn r8(&mut self, addr: u64) -> u8;
+    fn r8(&self, addr: u64) -> u8;
     /// Read a 16-bit value from `addr`
-    fn r16(&mut self, addr: u64) -> u16;
+    fn r16(&self, addr: u64) -> u16;
     /// Read a 32-bit value from `addr`
-    fn r32(&mut self, addr: u64) -> u32;
+    fn r32(&self, addr: u64) -> u32;
     /// Read a 64-bit value from `addr`
-    fn r64(&mut self, addr: u64) -> u64;
+    fn r64(&self, addr: u64) -> u64;
     /// Read a 128-bit value from `addr`
-    fn r128(&mut self, addr: u64) -> u128;
+    fn r128(&self, addr: u64) -> u128;
 
     /// Write a 8-bit `val` to `addr`
     fn w8(&mut self, addr: u64, val: u8);
@@ -22,35 +23,150 @@ pub trait Memory {
     fn w64(&mut self, addr: u64, val: u64);
     /// Write a 128-bit `val` to `addr`
     fn w128(&mut self, addr: u64, val: u128);
+
+    /// Whether the capability slot at `slot` (which must be aligned) is
+    /// tagged.
+    fn tag(&self, slot: u64) -> bool;
+    /// Set or clear the tag of the capability slot at `slot`.
+    fn set_tag(&mut self, slot: u64, tag: bool);
+}
+
+/// Bytes per block of `SimpleMemory`.
+const BLOCK_SIZE: u64 = 64;
+
+#[derive(Clone)]
+struct Block {
+    bytes: [u8; BLOCK_SIZE as usize],
+    // Bit `i` is set if byte `i` has been written.
+    known: u64,
+}
+
+impl Default for Block {
+    fn default() -> Self {
+        Block {
+            bytes: [0; BLOCK_SIZE as usize],
+            known: 0,
+        }
+    }
 }
 
-// It's more efficient to use blocks of about 64 bytes but this will do for now.
 #[derive(Default, Clone)]
-pub struct SimpleMemory(HashMap<u64, u8>);
+pub struct SimpleMemory {
+    // Contents reconstructed from the ELF and the trace, in blocks of
+    // `BLOCK_SIZE` bytes indexed by `addr / BLOCK_SIZE`.
+    data: HashMap<u64, Block>,
+    // Bytes written by the debugger. These shadow `data` for `read_patched()`
+    // but are ignored by the `Memory` trait, so replaying the trace (in
+    // either direction) always sees the real values.
+    overlay: BTreeMap<u64, u8>,
+    // Addresses of tagged capability slots.
+    tags: HashSet<u64>,
+}
+
+impl SimpleMemory {
+    /// Read a byte as the debugger should see it, i.e. including patches.
+    pub fn read_patched(&self, addr: u64) -> u8 {
+        match self.overlay.get(&addr) {
+            Some(val) => *val,
+            None => self.r8(addr),
+        }
+    }
+
+    /// Read `buf.len()` bytes from `addr` like `read_patched()`.
+    pub fn read_patched_into(&self, addr: u64, buf: &mut [u8]) {
+        let mut done = 0;
+        while done < buf.len() {
+            let at = addr + done as u64;
+            let offset = (at % BLOCK_SIZE) as usize;
+            let len = (BLOCK_SIZE as usize - offset).min(buf.len() - done);
+            let chunk = &mut buf[done..done + len];
+            match self.data.get(&(at / BLOCK_SIZE)) {
+                Some(block) => chunk.copy_from_slice(&block.bytes[offset..offset + len]),
+                None => chunk.fill(0),
+            }
+            done += len;
+        }
+
+        if !self.overlay.is_empty() {
+            let end = addr.saturating_add(buf.len() as u64);
+            for (patch_addr, val) in self.overlay.range(addr..end) {
+                buf[(patch_addr - addr) as usize] = *val;
+            }
+        }
+    }
+
+    /// Write a byte to the overlay.
+    pub fn patch(&mut self, addr: u64, val: u8) {
+        self.overlay.insert(addr, val);
+    }
+
+    /// All patched bytes, in no particular order.
+    pub fn patches(&self) -> impl Iterator<Item = (u64, u8)> + '_ {
+        self.overlay.iter().map(|(addr, val)| (*addr, *val))
+    }
+
+    /// Contiguous runs of known bytes (including patches), sorted by
+    /// address and optionally limited to `range`.
+    pub fn segments(&self, range: Option<Range<u64>>) -> Vec<(u64, Vec<u8>)> {
+        let known = self.data.iter().flat_map(|(index, block)| {
+            (0..BLOCK_SIZE)
+                .filter(|offset| block.known & (1 << offset) != 0)
+                .map(move |offset| index * BLOCK_SIZE + offset)
+        });
+        let mut addrs: Vec<u64> = known
+            .chain(self.overlay.keys().copied())
+            .filter(|addr| range.as_ref().is_none_or(|range| range.contains(addr)))
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+
+        let mut segments: Vec<(u64, Vec<u8>)> = Vec::new();
+        for addr in addrs {
+            let val = self.read_patched(addr);
+            match segments.last_mut() {
+                Some((start, data)) if *start + data.len() as u64 == addr => data.push(val),
+                _ => segments.push((addr, vec![val])),
+            }
+        }
+        segments
+    }
+
+    /// Discard all patches, returning the number of bytes discarded.
+    pub fn clear_overlay(&mut self) -> usize {
+        let n = self.overlay.len();
+        self.overlay.clear();
+        n
+    }
+}
 
 impl Memory for SimpleMemory {
-    fn r8(&mut self, addr: u64) -> u8 {
-        *self.0.get(&addr).unwrap_or(&0)
+    fn r8(&self, addr: u64) -> u8 {
+        self.data
+            .get(&(addr / BLOCK_SIZE))
+            .map_or(0, |block| block.bytes[(addr % BLOCK_SIZE) as usize])
     }
 
-    fn r16(&mut self, addr: u64) -> u16 {
+    fn r16(&self, addr: u64) -> u16 {
         self.r8(addr) as u16 | (self.r8(addr + 1) as u16) << 8
     }
 
-    fn r32(&mut self, addr: u64) -> u32 {
+    fn r32(&self, addr: u64) -> u32 {
         self.r16(addr) as u32 | (self.r16(addr + 2) as u32) << 16
     }
 
-    fn r64(&mut self, addr: u64) -> u64 {
+    fn r64(&self, addr: u64) -> u64 {
         self.r32(addr) as u64 | (self.r32(addr + 4) as u64) << 32
     }
 
-    fn r128(&mut self, addr: u64) -> u128 {
+    fn r128(&self, addr: u64) -> u128 {
         self.r64(addr) as u128 | (self.r64(addr + 8) as u128) << 64
     }
 
     fn w8(&mut self, addr: u64, val: u8) {
-        self.0.insert(addr, val);
+        let block = self.data.entry(addr / BLOCK_SIZE).or_default();
+        let offset = addr % BLOCK_SIZE;
+        block.bytes[offset as usize] = val;
+        block.known |= 1 << offset;
     }
 
     fn w16(&mut self, addr: u64, val: u16) {
@@ -72,4 +188,57 @@ impl Memory for SimpleMemory {
         self.w64(addr, val as u64);
         self.w64(addr + 8, (val >> 64) as u64);
     }
+
+    fn tag(&self, slot: u64) -> bool {
+        self.tags.contains(&slot)
+    }
+
+    fn set_tag(&mut self, slot: u64, tag: bool) {
+        if tag {
+            self.tags.insert(slot);
+        } else {
+            self.tags.remove(&slot);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_patched_into_blocks() {
+        let mut mem = SimpleMemory::default();
+        // Either side of a block boundary.
+        mem.w32(0x3e, 0x44332211);
+        mem.w8(0x80, 0x55);
+
+        let mut buf = [0xff; 0x50];
+        mem.read_patched_into(0x3c, &mut buf);
+        let mut expected = [0; 0x50];
+        expected[2..6].copy_from_slice(&[0x11, 0x22, 0x33, 0x44]);
+        expected[0x44] = 0x55;
+        assert_eq!(buf, expected);
+
+        // Patches shadow the data, but only inside the range.
+        mem.patch(0x3b, 0xaa);
+        mem.patch(0x3f, 0xbb);
+        mem.read_patched_into(0x3c, &mut buf);
+        expected[3] = 0xbb;
+        assert_eq!(buf, expected);
+        assert_eq!(mem.r8(0x3f), 0x22);
+    }
+
+    #[test]
+    fn segments_are_known_bytes() {
+        let mut mem = SimpleMemory::default();
+        mem.w16(0x3f, 0x2211);
+        mem.w8(0x100, 0);
+        mem.patch(0x41, 0xaa);
+        assert_eq!(
+            mem.segments(None),
+            [(0x3f, vec![0x11, 0x22, 0xaa]), (0x100, vec![0])]
+        );
+        assert_eq!(mem.segments(Some(0x40..0x100)), [(0x40, vec![0x22, 0xaa])]);
+    }
 }
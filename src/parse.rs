@@ -0,0 +1,12 @@
+//! Parsing shared by monitor commands, expressions and the text config files.
+
+use anyhow::{Context as _, Result};
+
+/// Parse a decimal or `0x` prefixed hex integer.
+pub fn parse_u64(s: &str) -> Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .with_context(|| format!("parsing {s:?}"))
+}
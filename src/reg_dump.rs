@@ -0,0 +1,207 @@
+//! Register dumps: simple `name=value` text files, one register per line.
+//! Names are ABI or architectural GPR names, CSR names (e.g. `mstatus`), or
+//! `pc`. Values are hex with a `0x` prefix, or decimal. Blank lines and
+//! lines starting with `#` are ignored.
+
+use std::path::Path;
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use num_traits::{FromPrimitive, Num, ToPrimitive as _};
+
+use crate::cpu::Cpu;
+use crate::machine::Machine;
+use crate::parse::parse_u64;
+use crate::riscv::RiscvArch;
+use crate::riscv::reg::names::{GPR_ABI_NAMES, csr_from_name, csr_name, gpr_from_name};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpReg {
+    Pc,
+    Gpr(u8),
+    Csr(u16),
+}
+
+impl std::fmt::Display for DumpReg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DumpReg::Pc => write!(f, "pc"),
+            DumpReg::Gpr(index) => write!(f, "{}", GPR_ABI_NAMES[*index as usize]),
+            DumpReg::Csr(csr) => match csr_name(*csr) {
+                Some(name) => write!(f, "{name}"),
+                None => write!(f, "csr {csr:#05x}"),
+            },
+        }
+    }
+}
+
+/// Read a register dump from `path`.
+pub fn read_reg_dump(path: &Path) -> Result<Vec<(DumpReg, u64)>> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+
+    let mut regs = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parse_line = || -> Result<(DumpReg, u64)> {
+            let (name, val) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected name=value"))?;
+            let name = name.trim();
+            let reg = if name == "pc" {
+                DumpReg::Pc
+            } else if let Some(index) = gpr_from_name(name) {
+                DumpReg::Gpr(index)
+            } else if let Some(csr) = csr_from_name(name) {
+                DumpReg::Csr(csr)
+            } else {
+                bail!("unknown register {name:?}");
+            };
+            Ok((reg, parse_u64(val.trim())?))
+        };
+
+        regs.push(
+            parse_line().with_context(|| {
+                format!("processing line {}:{}", path.display(), line_number + 1)
+            })?,
+        );
+    }
+
+    if regs.is_empty() {
+        bail!("no registers found in {}", path.display());
+    }
+
+    Ok(regs)
+}
+
+impl<A: RiscvArch> Machine<A> {
+    /// Compare the current registers against a register dump. Returns a
+    /// description of each mismatch.
+    pub fn check_regs(&self, path: &Path) -> Result<Vec<String>> {
+        let mut mismatches = Vec::new();
+
+        for (reg, expected) in read_reg_dump(path)? {
+            let actual = match reg {
+                DumpReg::Pc => self.cpu.pc,
+                DumpReg::Gpr(index) => self.cpu.xregs[index as usize],
+                DumpReg::Csr(csr) => match self.read_csr(csr) {
+                    Some(value) => value,
+                    None => {
+                        mismatches.push(format!(
+                            "{reg}: expected {expected:#x}, not written in the trace yet"
+                        ));
+                        continue;
+                    }
+                },
+            }
+            .to_u64()
+            .unwrap_or_default();
+
+            if actual != expected {
+                mismatches.push(format!(
+                    "{reg}: expected {expected:#x}, reconstructed {actual:#x}"
+                ));
+            }
+        }
+
+        Ok(mismatches)
+    }
+}
+
+/// Set the initial registers from a register dump, for traces that don't
+/// start from reset.
+pub fn apply_init_regs<Usize: Num + FromPrimitive>(
+    cpu: &mut Cpu<Usize>,
+    path: &Path,
+) -> Result<()> {
+    for (reg, value) in read_reg_dump(path)? {
+        let value = Usize::from_u64(value)
+            .ok_or_else(|| anyhow!("{reg} value {value:#x} in {} is too large", path.display()))?;
+        match reg {
+            DumpReg::Pc => cpu.pc = value,
+            // x0 is hardwired to zero.
+            DumpReg::Gpr(0) => bail!("x0 can't be set (in {})", path.display()),
+            DumpReg::Gpr(index) => cpu.xregs[index as usize] = value,
+            DumpReg::Csr(csr) => {
+                cpu.csrs.insert(csr, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{fixture, trace_machine};
+
+    /// Write `text` to a temporary dump file called `name`.
+    fn dump_file(name: &str, text: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rtd-{name}-{}", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn read_dump() {
+        let regs = read_reg_dump(&fixture("init_regs.txt")).unwrap();
+        assert_eq!(
+            regs,
+            [
+                (DumpReg::Pc, 0x80),
+                (DumpReg::Gpr(2), 0x2000),
+                (DumpReg::Csr(riscv_opcodes::CSR_MSTATUS), 0x1880),
+                (DumpReg::Gpr(10), 5),
+            ]
+        );
+
+        for (name, text, error) in [
+            ("unknown", "pc=0\nfoo=1\n", "unknown register \"foo\""),
+            ("no-equals", "pc 0\n", "expected name=value"),
+            ("empty", "# nothing\n\n", "no registers found"),
+        ] {
+            let path = dump_file(name, text);
+            let err = read_reg_dump(&path).unwrap_err();
+            assert!(format!("{err:#}").contains(error), "{err:#}");
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn check_regs() {
+        let mut machine = trace_machine("loop.log", Default::default());
+        machine.seek(3);
+
+        let path = dump_file("check", "pc=0x4\nsp=0xff0\na0=6\nmcause=0\n");
+        assert_eq!(
+            machine.check_regs(&path).unwrap(),
+            [
+                "a0: expected 0x6, reconstructed 0x5",
+                "mcause: expected 0x0, not written in the trace yet",
+            ]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn init_regs_errors() {
+        let mut cpu = Cpu::<u32>::default();
+        apply_init_regs(&mut cpu, &fixture("init_regs.txt")).unwrap();
+        assert_eq!(cpu.pc, 0x80);
+        assert_eq!(cpu.xregs[2], 0x2000);
+
+        for (name, text, error) in [
+            ("x0", "zero=1\n", "x0 can't be set"),
+            ("too-large", "a0=0x100000000\n", "a0 value 0x100000000"),
+        ] {
+            let path = dump_file(name, text);
+            let err = apply_init_regs(&mut Cpu::<u32>::default(), &path).unwrap_err();
+            assert!(format!("{err:#}").contains(error), "{err:#}");
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -6,6 +6,7 @@ use gdbstub::internal::{BeBytes, LeBytes};
 use num_traits::{FromPrimitive, PrimInt, Unsigned};
 use std::ops::AddAssign;
 
+use crate::riscv::reg::RiscvCoreRegs;
 use crate::riscv::reg::id::RiscvRegId;
 
 /// Extended version of `Arch` with more constraints (Usize: Default + Clone + Debug)
@@ -21,7 +22,18 @@ pub trait RiscvArch {
         + BeBytes
         + LeBytes
         + AddAssign;
-    type BaseArch: Arch<Usize = Self::Usize, RegId = RiscvRegId<Self::Usize>>;
+    type BaseArch: Arch<
+            Usize = Self::Usize,
+            RegId = RiscvRegId<Self::Usize>,
+            Registers = RiscvCoreRegs<Self::Usize>,
+        >;
+
+    /// Short name for messages, e.g. `RV32`.
+    const NAME: &'static str;
+    /// Whether registers are CHERI capabilities.
+    const CHERI: bool;
+    /// The file that `target_description_xml()` comes from.
+    const TARGET_XML: &'static str;
 }
 
 pub enum RiscvArch32 {}
@@ -32,21 +44,37 @@ pub enum RiscvCheriArch64 {}
 impl RiscvArch for RiscvArch32 {
     type Usize = u32;
     type BaseArch = RiscvArch32;
+
+    const NAME: &'static str = "RV32";
+    const CHERI: bool = false;
+    const TARGET_XML: &'static str = "rv32i.xml";
 }
 
 impl RiscvArch for RiscvArch64 {
     type Usize = u64;
     type BaseArch = RiscvArch64;
+
+    const NAME: &'static str = "RV64";
+    const CHERI: bool = false;
+    const TARGET_XML: &'static str = "rv64i.xml";
 }
 
 impl RiscvArch for RiscvCheriArch32 {
     type Usize = u32;
     type BaseArch = RiscvCheriArch32;
+
+    const NAME: &'static str = "RV32Y";
+    const CHERI: bool = true;
+    const TARGET_XML: &'static str = "rv32y.xml";
 }
 
 impl RiscvArch for RiscvCheriArch64 {
     type Usize = u64;
     type BaseArch = RiscvCheriArch64;
+
+    const NAME: &'static str = "RV64Y";
+    const CHERI: bool = true;
+    const TARGET_XML: &'static str = "rv64y.xml";
 }
 
 impl Arch for RiscvArch32 {
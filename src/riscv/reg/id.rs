@@ -15,6 +15,8 @@ pub enum RiscvRegId<U> {
     Csr(u16),
     /// Privilege level.
     Priv,
+    /// Vector register (v0-v31). These are VLEN bits so the size isn't fixed.
+    Vpr(u8),
 
     #[doc(hidden)]
     _Marker(core::marker::PhantomData<U>),
@@ -32,6 +34,7 @@ macro_rules! impl_riscv_reg_id {
                     33..=64 => (Self::Fpr((id - 33) as u8), USIZE),
                     65..=4160 => (Self::Csr((id - 65) as u16), USIZE),
                     4161 => (Self::Priv, 1),
+                    4162..=4193 => return Some((Self::Vpr((id - 4162) as u8), None)),
                     _ => return None,
                 };
 
@@ -3,6 +3,9 @@
 /// `RegId` definitions for RISC-V architectures.
 pub mod id;
 
+/// Register names, as used in the target description XML and register dumps.
+pub mod names;
+
 mod riscv;
 
 pub use riscv::RiscvCoreRegs;
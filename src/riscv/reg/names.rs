@@ -0,0 +1,118 @@
+/// ABI names of the general purpose registers, indexed by register number.
+pub const GPR_ABI_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "fp", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// Parse a general purpose register name, either an ABI name (`sp`) or an
+/// architectural name (`x2`).
+pub fn gpr_from_name(name: &str) -> Option<u8> {
+    if name == "s0" {
+        return Some(8);
+    }
+    if let Some(index) = GPR_ABI_NAMES.iter().position(|n| *n == name) {
+        return Some(index as u8);
+    }
+    let index: u8 = name.strip_prefix('x')?.parse().ok()?;
+    (index < 32).then_some(index)
+}
+
+/// Names of the standard user, supervisor, machine and debug CSRs, plus the
+/// CHERIoT stack high water mark CSRs.
+pub const CSR_NAMES: &[(u16, &str)] = &[
+    (0x001, "fflags"),
+    (0x002, "frm"),
+    (0x003, "fcsr"),
+    (0x100, "sstatus"),
+    (0x104, "sie"),
+    (0x105, "stvec"),
+    (0x106, "scounteren"),
+    (0x10a, "senvcfg"),
+    (0x140, "sscratch"),
+    (0x141, "sepc"),
+    (0x142, "scause"),
+    (0x143, "stval"),
+    (0x144, "sip"),
+    (0x180, "satp"),
+    (0x300, "mstatus"),
+    (0x301, "misa"),
+    (0x302, "medeleg"),
+    (0x303, "mideleg"),
+    (0x304, "mie"),
+    (0x305, "mtvec"),
+    (0x306, "mcounteren"),
+    (0x30a, "menvcfg"),
+    (0x310, "mstatush"),
+    (0x31a, "menvcfgh"),
+    (0x320, "mcountinhibit"),
+    (0x340, "mscratch"),
+    (0x341, "mepc"),
+    (0x342, "mcause"),
+    (0x343, "mtval"),
+    (0x344, "mip"),
+    (0x34a, "mtinst"),
+    (0x34b, "mtval2"),
+    (0x3a0, "pmpcfg0"),
+    (0x3a1, "pmpcfg1"),
+    (0x3a2, "pmpcfg2"),
+    (0x3a3, "pmpcfg3"),
+    (0x3b0, "pmpaddr0"),
+    (0x3b1, "pmpaddr1"),
+    (0x3b2, "pmpaddr2"),
+    (0x3b3, "pmpaddr3"),
+    (0x3b4, "pmpaddr4"),
+    (0x3b5, "pmpaddr5"),
+    (0x3b6, "pmpaddr6"),
+    (0x3b7, "pmpaddr7"),
+    (0x3b8, "pmpaddr8"),
+    (0x3b9, "pmpaddr9"),
+    (0x3ba, "pmpaddr10"),
+    (0x3bb, "pmpaddr11"),
+    (0x3bc, "pmpaddr12"),
+    (0x3bd, "pmpaddr13"),
+    (0x3be, "pmpaddr14"),
+    (0x3bf, "pmpaddr15"),
+    (0x747, "mseccfg"),
+    (0x7a0, "tselect"),
+    (0x7a1, "tdata1"),
+    (0x7a2, "tdata2"),
+    (0x7a3, "tdata3"),
+    (0x7b0, "dcsr"),
+    (0x7b1, "dpc"),
+    (0x7b2, "dscratch0"),
+    (0x7b3, "dscratch1"),
+    (0xb00, "mcycle"),
+    (0xb02, "minstret"),
+    (0xb80, "mcycleh"),
+    (0xb82, "minstreth"),
+    (0xbc1, "mshwm"),
+    (0xbc2, "mshwmb"),
+    (0xc00, "cycle"),
+    (0xc01, "time"),
+    (0xc02, "instret"),
+    (0xc80, "cycleh"),
+    (0xc81, "timeh"),
+    (0xc82, "instreth"),
+    (0xf11, "mvendorid"),
+    (0xf12, "marchid"),
+    (0xf13, "mimpid"),
+    (0xf14, "mhartid"),
+    (0xf15, "mconfigptr"),
+];
+
+/// The name of a CSR, if it is in `CSR_NAMES`.
+pub fn csr_name(csr: u16) -> Option<&'static str> {
+    CSR_NAMES
+        .iter()
+        .find(|(num, _)| *num == csr)
+        .map(|(_, name)| *name)
+}
+
+/// Parse a CSR name from `CSR_NAMES`.
+pub fn csr_from_name(name: &str) -> Option<u16> {
+    CSR_NAMES
+        .iter()
+        .find(|(_, n)| *n == name)
+        .map(|(num, _)| *num)
+}
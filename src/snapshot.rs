@@ -0,0 +1,222 @@
+//! Saving and restoring the replay position so it can be shared with
+//! someone who has the same ELF and trace.
+//!
+//! The file format is little endian binary:
+//!
+//! * Magic `RTDSTATE`
+//! * Version (u32)
+//! * XLEN in bytes (u8)
+//! * Trace hash (u64)
+//! * Trace index (u64)
+//! * PC (u64)
+//! * X registers (32 x u64)
+//! * F registers (32 x u64)
+//! * CSR count (u32) followed by (number: u16, value: u64) pairs
+//! * Overlay byte count (u64) followed by (address: u64, value: u8) pairs
+
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use num_traits::{FromPrimitive as _, ToPrimitive};
+
+use crate::machine::Machine;
+use crate::riscv::RiscvArch;
+use crate::trace::TraceEvent;
+
+const MAGIC: &[u8; 8] = b"RTDSTATE";
+const VERSION: u32 = 1;
+
+/// A hash of the trace so we can check that a saved state is being loaded
+/// with the same trace. This is FNV-1a rather than `DefaultHasher` because
+/// that isn't guaranteed to be stable across Rust versions.
+fn trace_hash<Usize: ToPrimitive>(trace: &[TraceEvent<Usize>]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut add = |val: u64| {
+        for b in val.to_le_bytes() {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+
+    add(trace.len() as u64);
+    for event in trace {
+        add(event.time);
+        add(event.cycle);
+        add(event.pc.to_u64().unwrap_or_default());
+        add(event.instruction.map_or(u64::MAX, |i| i as u64));
+    }
+    hash
+}
+
+fn read_u8(r: &mut impl Read) -> Result<u8> {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u16(r: &mut impl Read) -> Result<u16> {
+    let mut buf = [0; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32(r: &mut impl Read) -> Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+impl<A: RiscvArch> Machine<A> {
+    /// Save the current position, registers and memory overlay to `path`.
+    pub fn save_state(&self, path: &Path) -> Result<()> {
+        let file =
+            std::fs::File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        let mut w = BufWriter::new(file);
+
+        let to_u64 = |val: A::Usize| val.to_u64().unwrap_or_default();
+
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&[size_of::<A::Usize>() as u8])?;
+        w.write_all(&trace_hash(&self.trace).to_le_bytes())?;
+        w.write_all(&(self.trace_index as u64).to_le_bytes())?;
+
+        w.write_all(&to_u64(self.cpu.pc).to_le_bytes())?;
+        for reg in self.cpu.xregs.iter().chain(self.cpu.fregs.iter()) {
+            w.write_all(&to_u64(*reg).to_le_bytes())?;
+        }
+
+        let mut csrs: Vec<_> = self.cpu.csrs.iter().collect();
+        csrs.sort();
+        w.write_all(&(csrs.len() as u32).to_le_bytes())?;
+        for (num, val) in csrs {
+            w.write_all(&num.to_le_bytes())?;
+            w.write_all(&to_u64(*val).to_le_bytes())?;
+        }
+
+        let mut patches: Vec<_> = self.mem.patches().collect();
+        patches.sort();
+        w.write_all(&(patches.len() as u64).to_le_bytes())?;
+        for (addr, val) in patches {
+            w.write_all(&addr.to_le_bytes())?;
+            w.write_all(&[val])?;
+        }
+
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Restore a state saved by `save_state`. The trace must be the same
+    /// one that was loaded when it was saved.
+    pub fn load_state(&mut self, path: &Path) -> Result<()> {
+        let file =
+            std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let mut r = BufReader::new(file);
+
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("not a saved state file");
+        }
+        let version = read_u32(&mut r)?;
+        if version != VERSION {
+            bail!("unsupported saved state version {version} (expected {VERSION})");
+        }
+        let xlen = read_u8(&mut r)? as usize;
+        if xlen != size_of::<A::Usize>() {
+            bail!(
+                "saved state is for XLEN={} but the ELF is XLEN={}",
+                xlen * 8,
+                size_of::<A::Usize>() * 8
+            );
+        }
+        if read_u64(&mut r)? != trace_hash(&self.trace) {
+            bail!("saved state was created with a different trace");
+        }
+        let trace_index = read_u64(&mut r)? as usize;
+        if trace_index > self.trace.len() {
+            bail!("saved trace index {trace_index} is past the end of the trace");
+        }
+
+        let from_u64 = |val: u64| {
+            A::Usize::from_u64(val).ok_or_else(|| anyhow!("register value {val:#x} too large"))
+        };
+
+        let pc = from_u64(read_u64(&mut r)?)?;
+        let mut xregs = self.cpu.xregs;
+        for reg in xregs.iter_mut() {
+            *reg = from_u64(read_u64(&mut r)?)?;
+        }
+        let mut fregs = self.cpu.fregs;
+        for reg in fregs.iter_mut() {
+            *reg = from_u64(read_u64(&mut r)?)?;
+        }
+
+        let mut csrs = std::collections::HashMap::new();
+        for _ in 0..read_u32(&mut r)? {
+            let num = read_u16(&mut r)?;
+            csrs.insert(num, from_u64(read_u64(&mut r)?)?);
+        }
+
+        let mut patches = Vec::new();
+        for _ in 0..read_u64(&mut r)? {
+            let addr = read_u64(&mut r)?;
+            patches.push((addr, read_u8(&mut r)?));
+        }
+
+        // Everything has been read successfully so now we can actually
+        // modify the state. Replaying the trace reconstructs memory.
+        self.selected_frame = None;
+        self.seek(trace_index);
+
+        self.cpu.pc = pc;
+        self.cpu.xregs = xregs;
+        self.cpu.fregs = fregs;
+        self.cpu.csrs = csrs;
+
+        self.mem.clear_overlay();
+        for (addr, val) in patches {
+            self.mem.patch(addr, val);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Memory as _;
+    use crate::test_util::trace_machine;
+
+    #[test]
+    fn save_load_round_trip() {
+        let path = std::env::temp_dir().join(format!("rtd-state-{}", std::process::id()));
+
+        let mut saved = trace_machine("loop.log", Default::default());
+        saved.seek(5);
+        saved.mem.patch(0x2000, 0x42);
+        saved.save_state(&path).unwrap();
+
+        let mut loaded = trace_machine("loop.log", Default::default());
+        loaded.load_state(&path).unwrap();
+        assert_eq!(loaded.trace_index, 5);
+        assert_eq!(loaded.cpu.pc, saved.cpu.pc);
+        assert_eq!(loaded.cpu.xregs, saved.cpu.xregs);
+        assert_eq!(loaded.mem.r8(0xff0), 5);
+        assert_eq!(loaded.mem.read_patched(0x2000), 0x42);
+
+        let err = trace_machine("amo.log", Default::default())
+            .load_state(&path)
+            .unwrap_err();
+        assert!(err.to_string().contains("different trace"), "{err}");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
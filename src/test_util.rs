@@ -0,0 +1,62 @@
+//! Helpers for tests that use the files in `tests/fixtures` or need a
+//! `Machine`. Also built with the `test-util` feature so the binary's tests
+//! can use them.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use tokio::sync::watch;
+
+use crate::machine::{Machine, MachineOptions, ReplayStatus};
+use crate::riscv::{RiscvArch, RiscvArch32};
+use crate::trace::TraceEvent;
+use crate::{cheriot_ibex_trace, ibex_trace};
+
+/// Path of a file in `tests/fixtures`.
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+/// Create a machine for the ELF `elf` from `tests/fixtures`.
+pub fn try_machine<A: RiscvArch>(
+    elf: &str,
+    trace: Vec<TraceEvent<A::Usize>>,
+    options: MachineOptions,
+) -> Result<Machine<A>> {
+    try_machine_with_status(elf, trace, options).map(|(machine, _)| machine)
+}
+
+/// Like `try_machine()` but also returns the receiver for status updates.
+pub fn try_machine_with_status<A: RiscvArch>(
+    elf: &str,
+    trace: Vec<TraceEvent<A::Usize>>,
+    options: MachineOptions,
+) -> Result<(Machine<A>, watch::Receiver<ReplayStatus>)> {
+    let elf = std::fs::read(fixture(elf))?;
+    let (send_status, status) = watch::channel(ReplayStatus::default());
+    Ok((Machine::new(elf, trace, options, send_status)?, status))
+}
+
+/// Like `try_machine()` but panics on errors.
+pub fn machine<A: RiscvArch>(
+    elf: &str,
+    trace: Vec<TraceEvent<A::Usize>>,
+    options: MachineOptions,
+) -> Machine<A> {
+    try_machine(elf, trace, options).unwrap()
+}
+
+/// An RV32 machine for `rv32.elf` replaying the Ibex trace `trace` from
+/// `tests/fixtures`.
+pub fn trace_machine(trace: &str, options: MachineOptions) -> Machine<RiscvArch32> {
+    let trace = ibex_trace::read_trace(&fixture(trace), &Default::default()).unwrap();
+    machine("rv32.elf", trace, options)
+}
+
+/// Like `trace_machine()` but for a CHERIoT Ibex trace.
+pub fn cheriot_trace_machine(trace: &str, options: MachineOptions) -> Machine<RiscvArch32> {
+    let trace = cheriot_ibex_trace::read_trace(&fixture(trace), &Default::default()).unwrap();
+    machine("rv32.elf", trace, options)
+}
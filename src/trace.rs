@@ -1,3 +1,16 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufRead as _, BufReader},
+    path::Path,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use log::warn;
+use num_traits::Num;
+
+use crate::decode;
+
 #[derive(Clone)]
 pub struct TraceEvent<Usize> {
     pub time: u64,
@@ -10,21 +23,178 @@ pub struct TraceEvent<Usize> {
     pub assembly_mnemonic: String,
     pub assembly_args: String,
     pub xwrite: Option<XRegWrite<Usize>>,
+    pub vwrite: Option<VRegWrite>,
     pub store: Option<MemWrite>,
+    // CSRs written by the hardware, e.g. `mcause` for a trap. Traces don't
+    // include these so they are inferred (see `infer_trap_csrs()`).
+    pub csr_writes: Vec<CsrWrite<Usize>>,
+    // The original line from the trace file, if `ReadOptions::keep_raw` is set.
+    pub raw: Option<String>,
+    // 1-based line number in the trace file.
+    pub line: u64,
+}
+
+/// Traces mark instructions that trap by prefixing the mnemonic with this.
+pub const TRAP_MARKER: &str = "-->";
+
+/// Split an `x<N>=<value>` access token into the register number (1 to 31)
+/// and the value.
+pub fn xreg_write_token(token: &str) -> Option<(u8, &str)> {
+    let (index, value) = token.strip_prefix('x')?.split_once('=')?;
+    if index.starts_with('0') || !index.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let index: u8 = index.parse().ok()?;
+    (1..32).contains(&index).then_some((index, value))
+}
+
+/// Whether a mnemonic from a trace has the trap marker.
+pub fn has_trap_marker(mnemonic: &str) -> bool {
+    mnemonic.trim_start().starts_with(TRAP_MARKER)
+}
+
+impl<Usize> TraceEvent<Usize> {
+    /// The mnemonic without the trap marker.
+    pub fn mnemonic(&self) -> &str {
+        let mnemonic = self.assembly_mnemonic.trim_start();
+        mnemonic
+            .strip_prefix(TRAP_MARKER)
+            .map_or(mnemonic, str::trim_start)
+    }
 }
 
 #[derive(Clone)]
 pub struct XRegWrite<Usize> {
     pub index: u8,
     pub value: Usize,
+    // Capability metadata, if the trace has it (CHERI only).
+    pub metadata: Option<CapMetadata>,
+    pub prev_value: Option<Usize>,
+    pub prev_metadata: Option<CapMetadata>,
+}
+
+#[derive(Clone)]
+pub struct CsrWrite<Usize> {
+    pub csr: u16,
+    pub value: Usize,
+    // The value before the write, or `None` if it wasn't known. Only valid
+    // once the event has been applied.
     pub prev_value: Option<Usize>,
 }
 
+/// How far after a trap to look for the handler reading `mcause` and
+/// `mtval`. Handlers usually save some registers first.
+const TRAP_CSR_LOOKAHEAD: usize = 256;
+
+/// Fill in the `mcause` and `mtval` written by each trap. Traces don't show
+/// these writes, but the handler reads them (e.g. `csrr t0, mcause`) soon
+/// after, and the value it gets is in the trace. We stop looking at the
+/// next trap or `mret`.
+pub fn infer_trap_csrs<Usize: Copy>(trace: &mut [TraceEvent<Usize>]) {
+    const TRAP_CSRS: [u16; 2] = [riscv_opcodes::CSR_MCAUSE, riscv_opcodes::CSR_MTVAL];
+
+    for index in 0..trace.len() {
+        if !trace[index].trap {
+            continue;
+        }
+        let mut writes: Vec<CsrWrite<Usize>> = Vec::new();
+        for event in trace.iter().skip(index + 1).take(TRAP_CSR_LOOKAHEAD) {
+            let Some(instruction) = event.instruction else {
+                continue;
+            };
+            if event.trap
+                || decode::matcher(instruction)(riscv_opcodes::MASK_MRET, riscv_opcodes::MATCH_MRET)
+            {
+                break;
+            }
+            if let (Some(csr), Some(xwrite)) = (decode::csr_accessed(instruction), &event.xwrite)
+                && TRAP_CSRS.contains(&csr)
+                && !writes.iter().any(|write| write.csr == csr)
+            {
+                writes.push(CsrWrite {
+                    csr,
+                    value: xwrite.value,
+                    prev_value: None,
+                });
+            }
+        }
+        trace[index].csr_writes = writes;
+    }
+}
+
+/// Whether `instruction` is a store-conditional (`sc.w`/`sc.d`) that failed,
+/// going by the value written to its destination register. A failed SC
+/// doesn't write memory, but a trace may still report the store it tried.
+pub fn is_failed_store_conditional<Usize: Num>(
+    instruction: u32,
+    xwrite: Option<&XRegWrite<Usize>>,
+) -> bool {
+    let is = decode::matcher(instruction);
+    (is(riscv_opcodes::MASK_SC_W, riscv_opcodes::MATCH_SC_W)
+        || is(riscv_opcodes::MASK_SC_D, riscv_opcodes::MATCH_SC_D))
+        && xwrite.is_some_and(|xwrite| !xwrite.value.is_zero())
+}
+
+/// A write to a vector register. The value is little endian and may be
+/// shorter than VLEN if the trace omits leading zeros.
+#[derive(Clone)]
+pub struct VRegWrite {
+    pub index: u8,
+    pub value: Vec<u8>,
+    pub prev_value: Option<Vec<u8>>,
+}
+
+impl VRegWrite {
+    /// Parse a `v<n>=0x...` access token, returning `None` if it isn't one.
+    pub fn parse(token: &str) -> Option<Result<Self>> {
+        let (name, value) = token.split_once('=')?;
+        let index: u8 = name.strip_prefix('v')?.parse().ok().filter(|i| *i < 32)?;
+
+        Some((|| {
+            let hex = value
+                .strip_prefix("0x")
+                .ok_or_else(|| anyhow!("hex integer missing 0x prefix {value:?}"))?;
+            if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                bail!("invalid hex integer {hex:?}");
+            }
+            // Pad to whole bytes, then reverse so the least significant
+            // byte is first.
+            let hex = if hex.len() % 2 == 1 {
+                format!("0{hex}")
+            } else {
+                hex.to_owned()
+            };
+            let value = (0..hex.len())
+                .step_by(2)
+                .rev()
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<Result<_, _>>()?;
+            Ok(Self {
+                index,
+                value,
+                prev_value: None,
+            })
+        })())
+    }
+}
+
+/// The upper half of a capability and its tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapMetadata {
+    pub tag: bool,
+    pub bits: u64,
+}
+
 #[derive(Clone)]
 pub struct MemWrite {
     pub phys_addr: u64,
     pub value: Data,
+    // The tag for capability stores (CHERI only).
+    pub tag: Option<bool>,
     pub prev_value: Option<Data>,
+    // Capability slots whose tags were changed by the store, with their
+    // previous tags.
+    pub prev_tags: Vec<(u64, bool)>,
 }
 
 #[derive(Clone)]
@@ -36,3 +206,278 @@ pub enum Data {
     // Needed for CHERI on RV64. And I guess some atomics/F128 etc.
     U128(u128),
 }
+
+impl Data {
+    /// Size in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Data::U8(_) => 1,
+            Data::U16(_) => 2,
+            Data::U32(_) => 4,
+            Data::U64(_) => 8,
+            Data::U128(_) => 16,
+        }
+    }
+
+    pub fn to_u128(&self) -> u128 {
+        match *self {
+            Data::U8(val) => val.into(),
+            Data::U16(val) => val.into(),
+            Data::U32(val) => val.into(),
+            Data::U64(val) => val.into(),
+            Data::U128(val) => val,
+        }
+    }
+}
+
+/// The tab-separated column that each field of a trace line is in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceColumns {
+    pub time: usize,
+    pub cycle: usize,
+    pub pc: usize,
+    pub insn: usize,
+    pub mnemonic: Option<usize>,
+    pub args: Option<usize>,
+    pub accesses: Option<usize>,
+}
+
+impl Default for TraceColumns {
+    /// The Ibex layout.
+    fn default() -> Self {
+        Self {
+            time: 0,
+            cycle: 1,
+            pc: 2,
+            insn: 3,
+            mnemonic: Some(4),
+            args: Some(5),
+            accesses: Some(6),
+        }
+    }
+}
+
+impl TraceColumns {
+    /// The minimum number of columns a line must have.
+    pub fn required_len(&self) -> usize {
+        [self.time, self.cycle, self.pc, self.insn]
+            .into_iter()
+            .max()
+            .unwrap_or_default()
+            + 1
+    }
+}
+
+impl std::str::FromStr for TraceColumns {
+    type Err = anyhow::Error;
+
+    /// Parse a spec like `time=0,cycle=1,pc=2,insn=3,accesses=6`. `time`,
+    /// `cycle`, `pc` and `insn` are required; `mnemonic`, `args` and
+    /// `accesses` are optional.
+    fn from_str(spec: &str) -> Result<Self> {
+        const NAMES: [&str; 7] = [
+            "time", "cycle", "pc", "insn", "mnemonic", "args", "accesses",
+        ];
+        let mut columns: [Option<usize>; 7] = [None; 7];
+
+        for entry in spec.split(',') {
+            let (name, index) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected name=column, got {entry:?}"))?;
+            let field = NAMES
+                .iter()
+                .position(|n| *n == name.trim())
+                .ok_or_else(|| anyhow!("unknown trace column {name:?}"))?;
+            let index = index
+                .trim()
+                .parse()
+                .with_context(|| format!("parsing column index {index:?}"))?;
+            if columns[field].replace(index).is_some() {
+                bail!("trace column {name:?} given more than once");
+            }
+        }
+
+        let required = |field: usize| {
+            columns[field]
+                .ok_or_else(|| anyhow!("missing required trace column {:?}", NAMES[field]))
+        };
+
+        Ok(Self {
+            time: required(0)?,
+            cycle: required(1)?,
+            pc: required(2)?,
+            insn: required(3)?,
+            mnemonic: columns[4],
+            args: columns[5],
+            accesses: columns[6],
+        })
+    }
+}
+
+/// Options for reading trace files.
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    /// Only read this many events and ignore the rest of the file.
+    pub max_events: Option<usize>,
+    pub columns: TraceColumns,
+    /// Store the original line in `TraceEvent::raw`.
+    pub keep_raw: bool,
+    /// Decode the mnemonic of instructions that don't have one.
+    pub decode: bool,
+}
+
+/// Access tokens that a parser didn't recognise, counted by prefix (e.g.
+/// `csr:` or `f3=`) so we can tell the user what is being ignored.
+#[derive(Debug, Default)]
+pub struct UnknownTokens(BTreeMap<String, usize>);
+
+impl UnknownTokens {
+    pub fn add(&mut self, token: &str) {
+        let prefix = match token.find([':', '=']) {
+            Some(end) => &token[..=end],
+            None => token,
+        };
+        *self.0.entry(prefix.to_owned()).or_default() += 1;
+    }
+
+    /// A summary of the tokens for a warning, e.g. `"csr:" x2, "foo:" x1`,
+    /// or `None` if there weren't any.
+    pub fn summary(&self) -> Option<String> {
+        let summary: Vec<String> = self
+            .0
+            .iter()
+            .map(|(prefix, count)| format!("{prefix:?} x{count}"))
+            .collect();
+        (!summary.is_empty()).then(|| summary.join(", "))
+    }
+}
+
+/// Read a trace file, skipping the header and parsing each line with
+/// `read_line`.
+pub fn read_trace_file<Usize>(
+    file_path: &Path,
+    options: &ReadOptions,
+    read_line: impl Fn(&str, &mut UnknownTokens) -> Result<TraceEvent<Usize>>,
+) -> Result<Vec<TraceEvent<Usize>>> {
+    let file = File::open(file_path)?;
+    let reader = BufReader::new(file);
+
+    let mut events = Vec::new();
+    let mut unknown = UnknownTokens::default();
+
+    for (line_number, line) in reader.lines().enumerate() {
+        if options.max_events.is_some_and(|max| events.len() >= max) {
+            break;
+        }
+
+        let line_number_plus_one = line_number + 1;
+        let line = line.with_context(|| {
+            format!(
+                "reading line {}:{line_number_plus_one}",
+                file_path.display()
+            )
+        })?;
+
+        if line.starts_with("Time") {
+            // Skip header.
+            continue;
+        }
+
+        let mut event = read_line(&line, &mut unknown).with_context(|| {
+            format!(
+                "processing line {}:{line_number_plus_one}",
+                file_path.display()
+            )
+        })?;
+        event.line = line_number_plus_one as u64;
+        if options.keep_raw {
+            event.raw = Some(line);
+        }
+        if options.decode
+            && event.assembly_mnemonic.is_empty()
+            && let Some(mnemonic) = event
+                .instruction
+                .and_then(|insn| decode::mnemonic(insn, size_of::<Usize>() * 8))
+        {
+            event.assembly_mnemonic = mnemonic.to_owned();
+            event.assembly_args = decode::DECODED_ARGS.to_owned();
+        }
+        events.push(event);
+    }
+
+    if let Some(summary) = unknown.summary() {
+        warn!(
+            "Ignored unrecognised access tokens in {}: {summary}",
+            file_path.display(),
+        );
+    }
+
+    if events.is_empty() {
+        warn!(
+            "{} has no trace events so there is no execution to replay",
+            file_path.display()
+        );
+    }
+
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xreg_write_tokens() {
+        assert_eq!(xreg_write_token("x5=0x1"), Some((5, "0x1")));
+        assert_eq!(xreg_write_token("x31=0x1"), Some((31, "0x1")));
+        for token in [
+            "x0=0x1", "x32=0x1", "x05=0x1", "x+1=0x1", "x=0x1", "y1=0x1", "x1",
+        ] {
+            assert_eq!(xreg_write_token(token), None, "{token}");
+        }
+    }
+
+    #[test]
+    fn trace_columns_spec() {
+        let columns: TraceColumns = "pc=0, time=1,cycle=2,insn=3,accesses=4".parse().unwrap();
+        assert_eq!(
+            columns,
+            TraceColumns {
+                time: 1,
+                cycle: 2,
+                pc: 0,
+                insn: 3,
+                mnemonic: None,
+                args: None,
+                accesses: Some(4),
+            }
+        );
+        assert_eq!(columns.required_len(), 4);
+
+        for (spec, error) in [
+            (
+                "time=0,cycle=1,pc=2",
+                "missing required trace column \"insn\"",
+            ),
+            ("time=0,cycle=1,pc=2,insn=3,time=4", "given more than once"),
+            ("time=0,cycle=1,pc=2,insn=3,foo=4", "unknown trace column"),
+            ("time=0,cycle=1,pc=2,insn", "expected name=column"),
+        ] {
+            let err = spec.parse::<TraceColumns>().unwrap_err();
+            assert!(err.to_string().contains(error), "{spec}: {err}");
+        }
+    }
+
+    #[test]
+    fn unknown_tokens() {
+        let mut unknown = UnknownTokens::default();
+        assert_eq!(unknown.summary(), None);
+        for token in ["foo:0x1", "csr:mstatus=1", "foo:0x2", "f3=0x1", "bar"] {
+            unknown.add(token);
+        }
+        assert_eq!(
+            unknown.summary().as_deref(),
+            Some(r#""bar" x1, "csr:" x1, "f3=" x1, "foo:" x2"#)
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Mapping from trap causes to the signal reported to GDB.
+//!
+//! The mapping can be overridden by a file with one `kind:code=signal` entry
+//! per line, where `kind` is `mcause` (matched against the whole `mcause`
+//! value) or `cheri` (matched against the CHERIoT exception code in `mtval`
+//! when `mcause` is a CHERI fault). `signal` is a name like `SIGSEGV` or a
+//! GDB signal number. For example:
+//!
+//! ```text
+//! # Illegal instructions
+//! mcause:2=SIGILL
+//! # Bounds violations
+//! cheri:0x01=SIGSEGV
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use gdbstub::common::Signal;
+
+use crate::parse::parse_u64;
+
+/// `mcause` value for CHERI exceptions on CHERIoT. The exception code is in
+/// `mtval[4:0]`.
+pub const MCAUSE_CHERI: u64 = 0x1c;
+
+#[derive(Debug, Clone)]
+pub struct TrapSignals {
+    mcause: HashMap<u64, Signal>,
+    cheri: HashMap<u64, Signal>,
+}
+
+impl Default for TrapSignals {
+    fn default() -> Self {
+        let mcause = HashMap::from([
+            (0x0, Signal::SIGBUS),  // Instruction address misaligned
+            (0x1, Signal::SIGSEGV), // Instruction access fault
+            (0x2, Signal::SIGILL),  // Illegal instruction
+            (0x3, Signal::SIGTRAP), // Breakpoint
+            (0x4, Signal::SIGBUS),  // Load address misaligned
+            (0x5, Signal::SIGSEGV), // Load access fault
+            (0x6, Signal::SIGBUS),  // Store address misaligned
+            (0x7, Signal::SIGSEGV), // Store access fault
+            (0xc, Signal::SIGSEGV), // Instruction page fault
+            (0xd, Signal::SIGSEGV), // Load page fault
+            (0xf, Signal::SIGSEGV), // Store page fault
+        ]);
+        let cheri = HashMap::from([
+            (0x01, Signal::SIGSEGV), // Bounds violation
+            (0x02, Signal::SIGBUS),  // Tag violation
+            (0x03, Signal::SIGBUS),  // Seal violation
+            (0x11, Signal::SIGSEGV), // Permit execute violation
+            (0x12, Signal::SIGSEGV), // Permit load violation
+            (0x13, Signal::SIGSEGV), // Permit store violation
+            (0x15, Signal::SIGSEGV), // Permit store capability violation
+            (0x16, Signal::SIGSEGV), // Permit store local capability violation
+            (0x18, Signal::SIGILL),  // Permit access system registers violation
+        ]);
+        Self { mcause, cheri }
+    }
+}
+
+/// Parse a signal name (e.g. `SIGSEGV`) or number.
+fn parse_signal(name: &str) -> Result<Signal> {
+    if let Ok(number) = name.parse() {
+        return Ok(Signal(number));
+    }
+    // gdbstub doesn't provide a way to look up signals by name, but its
+    // `Display` implementation starts with the name.
+    (0..=u8::MAX)
+        .map(Signal)
+        .find(|signal| signal.to_string().split(' ').next() == Some(name))
+        .ok_or_else(|| anyhow!("unknown signal {name:?}"))
+}
+
+impl TrapSignals {
+    /// Load the default mapping, overridden by entries in `path`.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+
+        let mut signals = Self::default();
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parse_line = || -> Result<()> {
+                let (key, signal) = line
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("expected kind:code=signal"))?;
+                let signal = parse_signal(signal.trim())?;
+                match key.trim().split_once(':') {
+                    Some(("mcause", code)) => signals.mcause.insert(parse_u64(code)?, signal),
+                    Some(("cheri", code)) => signals.cheri.insert(parse_u64(code)?, signal),
+                    _ => bail!("expected `mcause:<code>` or `cheri:<code>`, got {key:?}"),
+                };
+                Ok(())
+            };
+
+            parse_line().with_context(|| {
+                format!("processing line {}:{}", path.display(), line_number + 1)
+            })?;
+        }
+
+        Ok(signals)
+    }
+
+    /// The signal for a trap with the given `mcause` and `mtval`. Unknown
+    /// causes are reported as `SIGTRAP`.
+    pub fn signal(&self, mcause: Option<u64>, mtval: Option<u64>) -> Signal {
+        let signal = match mcause {
+            Some(MCAUSE_CHERI) => mtval.and_then(|mtval| self.cheri.get(&(mtval & 0x1f))),
+            Some(mcause) => self.mcause.get(&mcause),
+            None => None,
+        };
+        signal.copied().unwrap_or(Signal::SIGTRAP)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::fixture;
+
+    #[test]
+    fn default_signals() {
+        let signals = TrapSignals::default();
+        assert_eq!(signals.signal(Some(0x2), None), Signal::SIGILL);
+        assert_eq!(
+            signals.signal(Some(MCAUSE_CHERI), Some(0x01)),
+            Signal::SIGSEGV
+        );
+        // Only mtval[4:0] is the CHERI exception code.
+        assert_eq!(
+            signals.signal(Some(MCAUSE_CHERI), Some(0x22)),
+            Signal::SIGBUS
+        );
+        assert_eq!(signals.signal(Some(0x99), None), Signal::SIGTRAP);
+        assert_eq!(signals.signal(None, None), Signal::SIGTRAP);
+    }
+
+    #[test]
+    fn signals_from_file() {
+        let signals = TrapSignals::from_file(&fixture("trap_signals.txt")).unwrap();
+        assert_eq!(
+            signals.signal(Some(MCAUSE_CHERI), Some(0x01)),
+            Signal::SIGUSR1
+        );
+        // Entries that aren't overridden keep their defaults.
+        assert_eq!(signals.signal(Some(0x2), None), Signal::SIGILL);
+    }
+
+    #[test]
+    fn parse_signals() {
+        assert_eq!(parse_signal("SIGSEGV").unwrap(), Signal::SIGSEGV);
+        assert_eq!(parse_signal("5").unwrap(), Signal::SIGTRAP);
+        assert!(parse_signal("SIGNOPE").is_err());
+    }
+}
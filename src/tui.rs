@@ -0,0 +1,127 @@
+//! A terminal status panel showing the replay position while a debugger is
+//! connected. Enabled with `--tui`.
+//!
+//! The terminal isn't put in raw mode so Ctrl-C still works, but log output
+//! is drawn over the panel unless it is redirected with `RTD_LOG_FILE`.
+
+use std::io::stdout;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::error;
+use ratatui::Frame;
+use ratatui::Terminal;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::cursor::{Hide, Show};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph};
+use tokio::select;
+use tokio::sync::oneshot;
+use tokio::sync::watch::Receiver;
+use tokio::task::JoinHandle;
+
+use riscv_trace_debugger::machine::ReplayStatus;
+
+/// How often to redraw while continuing. When stopped every update is drawn.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A running status panel. Call `stop()` to remove it.
+pub struct Tui {
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl Tui {
+    pub fn spawn(receive_status: Receiver<ReplayStatus>) -> Self {
+        let (shutdown, receive_shutdown) = oneshot::channel();
+        let task = tokio::task::spawn(async {
+            if let Err(e) = run(receive_status, receive_shutdown).await {
+                error!("{e:?}");
+            }
+        });
+        Self { shutdown, task }
+    }
+
+    /// Stop drawing and restore the terminal.
+    pub async fn stop(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.task.await;
+    }
+}
+
+/// Leaves the alternate screen when dropped, even if the task is cancelled.
+struct RestoreTerminal;
+
+impl Drop for RestoreTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), Show, LeaveAlternateScreen);
+    }
+}
+
+async fn run(
+    mut receive_status: Receiver<ReplayStatus>,
+    mut shutdown: oneshot::Receiver<()>,
+) -> Result<()> {
+    execute!(stdout(), EnterAlternateScreen, Hide)?;
+    let _restore = RestoreTerminal;
+
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+
+    let mut last_draw: Option<Instant> = None;
+
+    loop {
+        let status = *receive_status.borrow_and_update();
+        if !status.running || last_draw.is_none_or(|t| t.elapsed() >= REFRESH_INTERVAL) {
+            terminal.draw(|frame| draw(frame, &status))?;
+            last_draw = Some(Instant::now());
+        }
+
+        select! {
+            changed = receive_status.changed() => {
+                if changed.is_err() {
+                    // The machine has gone away.
+                    break;
+                }
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, status: &ReplayStatus) {
+    let block = Block::bordered().title(" riscv_trace_debugger ");
+    let area = block.inner(frame.area());
+    frame.render_widget(block, frame.area());
+
+    let [info_area, gauge_area] =
+        Layout::vertical([Constraint::Length(3), Constraint::Length(1)]).areas(area);
+
+    let direction = if status.reverse {
+        "backwards"
+    } else {
+        "forwards"
+    };
+    let state = if status.running { "running" } else { "stopped" };
+
+    let info = Paragraph::new(vec![
+        Line::from(format!("Time: {}  Cycle: {}", status.time, status.cycle)),
+        Line::from(format!("PC: {:#x}", status.pc)),
+        Line::from(format!("Direction: {direction} ({state})")),
+    ]);
+    frame.render_widget(info, info_area);
+
+    let ratio = if status.trace_len == 0 {
+        0.0
+    } else {
+        status.trace_index as f64 / status.trace_len as f64
+    };
+    let gauge = Gauge::default()
+        .ratio(ratio.clamp(0.0, 1.0))
+        .label(format!("{} / {}", status.trace_index, status.trace_len));
+    frame.render_widget(gauge, gauge_area);
+}
@@ -0,0 +1,138 @@
+#!/usr/bin/env python3
+"""Generate the minimal RISC-V ELF files used by the tests.
+
+They only have section headers (no program headers), which is all the
+debugger looks at. Run from this directory to regenerate them (this needs
+the `zstd` command).
+"""
+
+import gzip
+import struct
+import subprocess
+
+SHT_PROGBITS = 1
+SHT_STRTAB = 3
+SHT_NOBITS = 8
+SHF_WRITE = 0x1
+SHF_ALLOC = 0x2
+SHF_EXECINSTR = 0x4
+SHF_TLS = 0x400
+PT_LOAD = 1
+
+EM_RISCV = 243
+EF_RISCV_RVC = 0x1
+EF_RISCV_FLOAT_ABI_DOUBLE = 0x4
+
+# c.nop
+NOPS = bytes.fromhex("0100") * 32
+
+
+def elf(path, sections, is_64=False, flags=EF_RISCV_RVC, entry=0,
+        segments=()):
+    """Write an ELF with `sections`: a list of (name, type, flags, addr, data
+    or size for NOBITS), and program headers for `segments`: a list of
+    (type, offset, vaddr, size), where a size of None means the whole file.
+    The program headers go at the end of the file."""
+    ehsize, shentsize = (64, 64) if is_64 else (52, 40)
+    phentsize = (56 if is_64 else 32) if segments else 0
+
+    shstrtab = b"\0"
+    names = []
+    for name, *_ in sections + [(".shstrtab",)]:
+        names.append(len(shstrtab))
+        shstrtab += name.encode() + b"\0"
+
+    body = b""
+    offsets = []
+    for _, sh_type, _, _, data in sections:
+        offsets.append(ehsize + len(body))
+        if sh_type != SHT_NOBITS:
+            body += data
+    shstrtab_offset = ehsize + len(body)
+    body += shstrtab
+    while (ehsize + len(body)) % 8:
+        body += b"\0"
+    shoff = ehsize + len(body)
+    phoff = shoff + (len(sections) + 2) * shentsize if segments else 0
+    file_size = phoff + len(segments) * phentsize
+
+    ident = b"\x7fELF" + bytes([2 if is_64 else 1, 1, 1]) + bytes(9)
+    if is_64:
+        header = ident + struct.pack(
+            "<HHIQQQIHHHHHH", 2, EM_RISCV, 1, entry, phoff, shoff, flags,
+            ehsize, phentsize, len(segments), shentsize, len(sections) + 2,
+            len(sections) + 1)
+    else:
+        header = ident + struct.pack(
+            "<HHIIIIIHHHHHH", 2, EM_RISCV, 1, entry, phoff, shoff, flags,
+            ehsize, phentsize, len(segments), shentsize, len(sections) + 2,
+            len(sections) + 1)
+
+    def shdr(name, sh_type, sh_flags, addr, offset, size, align):
+        fmt = "<IIQQQQIIQQ" if is_64 else "<IIIIIIIIII"
+        return struct.pack(fmt, name, sh_type, sh_flags, addr, offset, size,
+                           0, 0, align, 0)
+
+    headers = shdr(0, 0, 0, 0, 0, 0, 0)
+    for name, (_, sh_type, sh_flags, addr, data), offset in zip(
+            names, sections, offsets):
+        size = data if sh_type == SHT_NOBITS else len(data)
+        headers += shdr(name, sh_type, sh_flags, addr, offset, size, 4)
+    headers += shdr(names[-1], SHT_STRTAB, 0, 0, shstrtab_offset,
+                    len(shstrtab), 1)
+
+    for p_type, offset, vaddr, size in segments:
+        size = file_size - offset if size is None else size
+        if is_64:
+            headers += struct.pack("<IIQQQQQQ", p_type, 4, offset, vaddr,
+                                   vaddr, size, size, 0x1000)
+        else:
+            headers += struct.pack("<IIIIIIII", p_type, offset, vaddr, vaddr,
+                                   size, size, 4, 0x1000)
+
+    with open(path, "wb") as f:
+        f.write(header + body + headers)
+
+
+text = (".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 0x0, NOPS)
+
+# TLS sections as a linker would lay them out: `.tbss` has the address
+# after `.tdata`, which is also where `.data` starts. The entry point isn't
+# where the traces start, like a trace that starts mid-program.
+elf("rv32.elf", [
+    text,
+    (".tdata", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE | SHF_TLS, 0x1000,
+     bytes.fromhex("11223344")),
+    (".tbss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE | SHF_TLS, 0x1004, 8),
+    (".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x1004,
+     bytes.fromhex("55667788")),
+], entry=0x20)
+
+elf("rv64.elf", [text], is_64=True,
+    flags=EF_RISCV_RVC | EF_RISCV_FLOAT_ABI_DOUBLE)
+
+# `.data` overlaps the end of `.text`.
+elf("overlap.elf", [
+    text,
+    (".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x30, bytes(0x20)),
+])
+
+# An empty section inside `.text`, which isn't an overlap.
+elf("empty_section.elf", [
+    text,
+    (".init_array", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x10, b""),
+])
+
+# Two segments, one of which contains the program headers so they are
+# loaded at 0x10000 + e_phoff.
+elf("segments.elf", [text], segments=[
+    (PT_LOAD, 0, 0x10000, None),
+    (PT_LOAD, 52, 0x0, len(NOPS)),
+])
+
+# Compressed copies of `rv32.elf`.
+with open("rv32.elf", "rb") as f:
+    plain = f.read()
+with open("rv32.elf.gz", "wb") as f:
+    f.write(gzip.compress(plain, mtime=0))
+subprocess.run(["zstd", "-q", "-f", "rv32.elf", "-o", "rv32.elf.zst"], check=True)